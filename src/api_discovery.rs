@@ -0,0 +1,83 @@
+use crate::backstage::{Api, CatalogFile};
+use anyhow::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::info;
+use std::path::Path;
+
+/// Globs for API definition files, and the backstage API type they define
+const DEFINITIONS: &[(&str, &str)] = &[
+    ("**/{openapi,swagger}.{yaml,yml,json}", "openapi"),
+    ("**/*.{openapi,swagger}.{yaml,yml,json}", "openapi"),
+    ("**/asyncapi.{yaml,yml,json}", "asyncapi"),
+    // client queries are `.graphql` files too, so only schemas count
+    ("**/{schema.graphql,*.graphqls}", "graphql"),
+    ("**/*.proto", "grpc"),
+];
+
+/// Directories holding third party code, whose definitions aren't provided by the repo
+const IGNORED: &[&str] = &["**/node_modules/**", "**/vendor/**", "**/third_party/**"];
+
+/// Adds an API entity for every definition file in the repo to the catalog file, and lists
+/// them in the component's `providesApis`
+pub(crate) fn add_provided_apis<'a>(
+    catalog: &mut CatalogFile,
+    paths: impl IntoIterator<Item = &'a str>,
+) -> Result<()> {
+    let definitions = DEFINITIONS
+        .iter()
+        .map(|(glob, _type)| Ok((Glob::new(glob)?.compile_matcher(), *_type)))
+        .collect::<Result<Vec<_>>>()?;
+    let ignored = glob_set(IGNORED)?;
+
+    for path in paths.into_iter().filter(|path| !ignored.is_match(path)) {
+        let Some((_, _type)) = definitions.iter().find(|(glob, _)| glob.is_match(path)) else {
+            continue;
+        };
+
+        let name = api_name(&catalog.component.metadata.name, path);
+        info!("{} provides {_type} API {name}", catalog.component.metadata.name);
+
+        if !catalog.contains("API", &name) {
+            let api = Api::new(&name, *_type, path, &catalog.component);
+            catalog.push(&api)?;
+        }
+
+        catalog.component.spec.provides_apis.insert(name);
+    }
+
+    Ok(())
+}
+
+fn glob_set(globs: &[&str]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Names the API after the component and the definition file, eg `my-service-openapi` for
+/// `openapi.yaml` or `my-service-proto-users` for `proto/users.proto`
+fn api_name(component: &str, path: &str) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+    let dir = path
+        .parent()
+        .and_then(|dir| dir.to_str())
+        .unwrap_or_default();
+
+    let name = [component, dir, stem]
+        .iter()
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("-");
+
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .take(63)
+        .collect();
+
+    name.trim_end_matches('-').to_owned()
+}
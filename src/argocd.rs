@@ -1,43 +1,429 @@
+use anyhow::{Context, Result};
+use serde::Serializer;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A document in an Argo CD manifest file
+#[derive(Debug)]
+pub enum Manifest {
+    Application(Box<ArgoApp>),
+    ApplicationSet(Box<ApplicationSet>),
+    AppProject(Box<AppProject>),
+    /// Any other kind of document, kept as is
+    Other(Value),
+}
+
+/// Parses every document in a manifest file, leaving out empty documents such as the one after
+/// a trailing `---`. Documents without a `kind` are read as applications, as in a `.argocd.yaml`
+/// holding a single app.
+pub fn parse_manifests(content: &str) -> Result<Vec<Manifest>> {
+    serde_yaml::Deserializer::from_str(content)
+        .map(Value::deserialize)
+        .filter(|document| !matches!(document, Ok(Value::Null)))
+        .map(|document| Manifest::from_value(document?))
+        .collect()
+}
+
+impl Manifest {
+    /// Reads a parsed document by its `kind`
+    pub fn from_value(document: Value) -> Result<Self> {
+        Ok(match document["kind"].as_str() {
+            Some("Application") | None => Manifest::Application(serde_yaml::from_value(document)?),
+            Some("ApplicationSet") => Manifest::ApplicationSet(serde_yaml::from_value(document)?),
+            Some("AppProject") => Manifest::AppProject(serde_yaml::from_value(document)?),
+            _ => Manifest::Other(document),
+        })
+    }
+}
+
+impl Serialize for Manifest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Manifest::Application(app) => app.serialize(serializer),
+            Manifest::ApplicationSet(app_set) => app_set.serialize(serializer),
+            Manifest::AppProject(project) => project.serialize(serializer),
+            Manifest::Other(document) => document.serialize(serializer),
+        }
+    }
+}
+
+/// Writes an edited document back out, keeping the `---` line the original document started with
+pub fn document_to_yaml(original: &str, document: &Value) -> Result<String> {
+    let mut yaml = String::new();
+    if original.starts_with("---") {
+        yaml.push_str(original.lines().next().unwrap_or_default());
+        yaml.push('\n');
+    }
+    yaml.push_str(&serde_yaml::to_string(document)?);
+    Ok(yaml)
+}
+
+/// The field of a mapping, added as null when it's missing. Null values become empty mappings.
+pub fn field<'a>(value: &'a mut Value, key: &str) -> Result<&'a mut Value> {
+    if value.is_null() {
+        *value = Value::Mapping(Mapping::new());
+    }
+
+    Ok(value
+        .as_mapping_mut()
+        .context(format!("expected a mapping holding {key}"))?
+        .entry(Value::from(key))
+        .or_insert(Value::Null))
+}
+
+/// Adds the strings the sequence doesn't have yet, a null value becomes a sequence
+pub fn push_missing(value: &mut Value, items: &[String]) -> Result<()> {
+    if value.is_null() {
+        *value = Value::Sequence(vec![]);
+    }
+
+    let sequence = value.as_sequence_mut().context("expected a list")?;
+    for item in items {
+        if !sequence.iter().any(|existing| existing.as_str() == Some(item)) {
+            sequence.push(Value::from(item.as_str()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits a multi document file into the text of each document, along with the `---` line
+/// starting it, so documents can be replaced without touching the rest of the file
+pub fn split_documents(content: &str) -> Vec<&str> {
+    let mut documents = vec![];
+    let (mut start, mut offset) = (0, 0);
+
+    for line in content.split_inclusive('\n') {
+        let line_content = line.trim_end_matches(['\r', '\n']);
+        let is_separator = line_content == "---"
+            || line_content.starts_with("--- ")
+            || line_content.starts_with("---\t");
+
+        if is_separator && offset > start {
+            documents.push(&content[start..offset]);
+            start = offset;
+        }
+        offset += line.len();
+    }
+
+    if start < content.len() {
+        documents.push(&content[start..]);
+    }
+    documents
+}
+
+// Every type keeps the fields it doesn't model in `extra`, so manifests round trip without
+// losing anything.
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ArgoApp {
     pub api_version: String,
+    #[serde(default = "ArgoApp::kind")]
+    pub kind: String,
     pub metadata: AppMetadata,
     pub spec: AppSpec,
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl ArgoApp {
+    fn kind() -> String {
+        "Application".to_owned()
+    }
+
+    /// The project of the app, apps without one belong to `default`
+    pub fn project(&self) -> &str {
+        self.spec.project.as_deref().unwrap_or("default")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplicationSet {
+    pub api_version: String,
+    pub kind: String,
+    pub metadata: AppMetadata,
+    pub spec: ApplicationSetSpec,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApplicationSetSpec {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub generators: Vec<Value>,
+    pub template: AppTemplate,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+/// The application an ApplicationSet generates, its fields may contain `{{...}}` placeholders
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AppTemplate {
+    pub metadata: AppMetadata,
+    pub spec: AppSpec,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AppMetadata {
     pub name: String,
-    #[serde(default)]
-    pub labels: HashMap<String, String>,
-    #[serde(default)]
-    pub annotations: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub finalizers: Vec<String>,
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct AppSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<AppSource>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<AppSource>,
     pub destination: AppDestination,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_policy: Option<SyncPolicy>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore_differences: Vec<IgnoreDifferences>,
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct AppDestination {
-    pub server: String,
-    pub namespace: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// Name of the cluster, used instead of `server`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct AppSource {
-    helm: AppHelmConfig,
+    #[serde(rename = "repoURL")]
+    pub repo_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_revision: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chart: Option<String>,
+    /// Name other sources of a multi source app use to refer to this one, eg `$values`
+    #[serde(default, rename = "ref", skip_serializing_if = "Option::is_none")]
+    pub _ref: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub helm: Option<HelmSource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kustomize: Option<KustomizeSource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub directory: Option<DirectorySource>,
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-enum AppHelmConfig {
-    ValueFiles(Vec<String>),
-    Values(String),
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HelmSource {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub value_files: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values_object: Option<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parameters: Vec<HelmParameter>,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HelmParameter {
+    pub name: String,
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub force_string: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct KustomizeSource {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_suffix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub common_labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub common_annotations: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirectorySource {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recurse: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<String>,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub automated: Option<AutomatedSync>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sync_options: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<Value>,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AutomatedSync {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prune: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub self_heal: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_empty: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IgnoreDifferences {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub json_pointers: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub jq_path_expressions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub managed_fields_managers: Vec<String>,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AppProject {
+    pub api_version: String,
+    pub kind: String,
+    pub metadata: AppMetadata,
+    pub spec: AppProjectSpec,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AppProjectSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_repos: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub destinations: Vec<AppDestination>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cluster_resource_whitelist: Vec<GroupKind>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespace_resource_blacklist: Vec<GroupKind>,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupKind {
+    pub group: String,
+    pub kind: String,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_document() {
+        let content = "---\napiVersion: argoproj.io/v1alpha1\nkind: AppProject\n\
+                       metadata:\n  name: web\nspec: {}\n---\nkind: ConfigMap\n---\n";
+        let manifests = parse_manifests(content).unwrap();
+
+        assert_eq!(manifests.len(), 2);
+        assert!(matches!(manifests[0], Manifest::AppProject(_)));
+        assert!(matches!(manifests[1], Manifest::Other(_)));
+    }
+
+    #[test]
+    fn reads_documents_without_kind_as_applications() {
+        let content = "apiVersion: argoproj.io/v1alpha1\nmetadata:\n  name: web\n\
+                       spec:\n  destination:\n    namespace: web\n";
+        let manifests = parse_manifests(content).unwrap();
+
+        let [Manifest::Application(app)] = manifests.as_slice() else {
+            panic!("expected a single application");
+        };
+        assert_eq!(app.metadata.name, "web");
+        assert_eq!(app.kind, "Application");
+    }
+
+    #[test]
+    fn splits_documents_with_their_separators() {
+        assert_eq!(
+            split_documents("a: 1\n--- # b\nb: 2\r\n---\r\nc: |\n  ---x\n"),
+            vec!["a: 1\n", "--- # b\nb: 2\r\n", "---\r\nc: |\n  ---x\n"]
+        );
+        assert_eq!(split_documents("---\na: 1\n"), vec!["---\na: 1\n"]);
+        assert!(split_documents("").is_empty());
+    }
+
+    #[test]
+    fn fields_replace_null_but_not_other_values() {
+        let mut value: Value = serde_yaml::from_str("spec:\nlabels: []\n").unwrap();
+
+        *field(field(&mut value, "spec").unwrap(), "project").unwrap() = Value::from("web");
+        assert_eq!(value["spec"]["project"].as_str(), Some("web"));
+        assert!(field(&mut value["labels"], "team").is_err());
+        assert!(push_missing(&mut value["spec"], &["a".to_owned()]).is_err());
+    }
 }
@@ -0,0 +1,365 @@
+use crate::{
+    argocd::{self, ApplicationSet, ArgoApp},
+    backstage::{CatalogFile, Component},
+    config::ComponentDefaults,
+    repo_files::RepoFiles,
+    repo_owner, Context,
+};
+use anyhow::{Context as anyhowContext, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::{debug, info, warn};
+use octocrab::models::Repository;
+use std::collections::BTreeMap;
+
+pub(crate) const APP_NAME: &str = "argocd/app-name";
+pub(crate) const APP_SELECTOR: &str = "argocd/app-selector";
+pub(crate) const LABEL_SELECTOR: &str = "backstage.io/kubernetes-label-selector";
+pub(crate) const NAMESPACE: &str = "backstage.io/kubernetes-namespace";
+
+/// The Argo CD applications and application sets defined in a repo
+#[derive(Default)]
+pub(crate) struct RepoApps {
+    pub apps: Vec<ArgoApp>,
+    pub app_sets: Vec<ApplicationSet>,
+}
+
+impl RepoApps {
+    /// Parses every file in the repo matching the configured manifest globs
+    pub async fn find(
+        ctx: &Context,
+        repo: &Repository,
+        paths: &[String],
+        files: Option<&RepoFiles>,
+    ) -> Result<Self> {
+        let owner = repo_owner(repo)?;
+        let globs = manifest_globs(ctx)?;
+        let mut found = Self::default();
+
+        for path in paths.iter().filter(|path| globs.is_match(path)) {
+            let content = match files {
+                Some(files) => files.get(path).map(str::to_owned),
+                None => ctx
+                    .client
+                    .get_file_content(owner, &repo.name, path)
+                    .await
+                    .context(format!("getting {} for {}/{}", path, owner, repo.name))?
+                    .decoded_content(),
+            };
+
+            info!("{} has {}", &repo.name, path);
+
+            let manifests = match argocd::parse_manifests(&content.unwrap_or_default()) {
+                Ok(manifests) => manifests,
+                Err(err) => {
+                    warn!(
+                        "skipping {} for {}/{}, it can't be parsed: {err:#}",
+                        path, owner, repo.name
+                    );
+                    continue;
+                }
+            };
+
+            for manifest in manifests {
+                match manifest {
+                    argocd::Manifest::Application(app) => found.apps.push(*app),
+                    argocd::Manifest::ApplicationSet(app_set) => found.app_sets.push(*app_set),
+                    argocd::Manifest::AppProject(project) => {
+                        debug!("{} defines project {}", &repo.name, project.metadata.name);
+                    }
+                    argocd::Manifest::Other(document) => {
+                        debug!("{} has a {:?} in {}", &repo.name, document["kind"], path);
+                    }
+                }
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// The project of the first app, used to work out the system
+    pub fn project(&self) -> Option<&str> {
+        self.apps.first().map(ArgoApp::project).or_else(|| {
+            self.app_sets
+                .first()
+                .map(|app_set| app_set.spec.template.spec.project.as_deref().unwrap_or("default"))
+        })
+    }
+
+    /// Labels set to the same value on every app and app set template
+    fn common_labels(&self) -> BTreeMap<String, String> {
+        let mut labels = self
+            .apps
+            .iter()
+            .map(|app| &app.metadata.labels)
+            .chain(
+                self.app_sets
+                    .iter()
+                    .map(|app_set| &app_set.spec.template.metadata.labels),
+            );
+
+        let Some(first) = labels.next() else {
+            return BTreeMap::new();
+        };
+
+        let mut common = first.clone();
+        for other in labels {
+            common.retain(|key, value| other.get(key) == Some(value));
+        }
+
+        // templated values differ between the generated apps
+        common.retain(|_, value| !value.contains("{{"));
+        common
+    }
+
+    /// The annotations linking a component to the apps, `None` for ones that must not be set.
+    /// Empty when the apps can't be selected as a whole. The Kubernetes label selector matches the
+    /// `app.kubernetes.io/instance` label Argo CD puts on what it deploys, so it is left out for
+    /// app sets, whose app names are templated.
+    fn expected_annotations(&self) -> BTreeMap<&'static str, Option<String>> {
+        if let ([app], []) = (self.apps.as_slice(), self.app_sets.as_slice()) {
+            let mut expected = app_annotations(app);
+            expected.insert(APP_SELECTOR, None);
+            return expected;
+        }
+
+        let labels = self.common_labels();
+
+        if labels.is_empty() {
+            return BTreeMap::new();
+        }
+
+        let selector = labels
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut expected = BTreeMap::from([(APP_NAME, None), (APP_SELECTOR, Some(selector))]);
+
+        if self.app_sets.is_empty() {
+            let names: Vec<_> = self.apps.iter().map(|app| app.metadata.name.as_str()).collect();
+            expected.insert(
+                LABEL_SELECTOR,
+                Some(format!("app.kubernetes.io/instance in ({})", names.join(","))),
+            );
+        }
+
+        expected
+    }
+
+    /// Compares the Kubernetes annotations of the component with the apps. When the repo no longer
+    /// has any apps, annotations added for them are stale, as long as the component was linked to
+    /// an app in the first place.
+    pub fn drift(&self, component: &Component) -> Vec<Drift> {
+        let annotations = &component.metadata.annotations;
+
+        let expected = if self.apps.is_empty() && self.app_sets.is_empty() {
+            if !annotations.contains_key(APP_NAME) && !annotations.contains_key(APP_SELECTOR) {
+                return vec![];
+            }
+            [APP_NAME, APP_SELECTOR, LABEL_SELECTOR, NAMESPACE]
+                .into_iter()
+                .map(|key| (key, None))
+                .collect()
+        } else {
+            self.expected_annotations()
+        };
+
+        expected
+            .into_iter()
+            .filter(|(key, value)| annotations.get(*key) != value.as_ref())
+            .map(|(key, expected)| Drift {
+                annotation: key,
+                current: annotations.get(key).cloned(),
+                expected,
+            })
+            .collect()
+    }
+
+    /// Annotates the component with the app it is deployed by. A single app is referenced by name,
+    /// several apps or an app set through the labels they share, and apps with nothing in common
+    /// each get their own component.
+    pub fn annotate(&self, catalog: &mut CatalogFile) -> Result<()> {
+        if self.apps.is_empty() && self.app_sets.is_empty() {
+            return Ok(());
+        }
+
+        let expected = self.expected_annotations();
+
+        if !expected.is_empty() {
+            insert_missing(&mut catalog.component, expected);
+            return Ok(());
+        }
+
+        if !self.app_sets.is_empty() {
+            warn!(
+                "{} has app sets without common labels, add labels to their templates to select them",
+                catalog.component.metadata.name
+            );
+            return Ok(());
+        }
+
+        let parent = &catalog.component;
+        let subcomponents: Vec<_> = self
+            .apps
+            .iter()
+            .filter(|app| !catalog.contains("Component", &app.metadata.name))
+            .map(|app| {
+                let mut subcomponent = Component::new(
+                    &app.metadata.name,
+                    format!("{} deployed by Argo CD", parent.metadata.name),
+                    ComponentDefaults {
+                        _type: parent.spec._type.to_owned(),
+                        lifecycle: parent.spec.lifecycle.to_owned(),
+                        owner: parent.spec.owner.to_owned(),
+                        system: parent.spec.system.to_owned(),
+                    },
+                );
+                subcomponent.spec.subcomponent_of = Some(parent.metadata.name.to_owned());
+                annotate_app(&mut subcomponent, app);
+                subcomponent
+            })
+            .collect();
+
+        for subcomponent in subcomponents {
+            catalog.push(&subcomponent)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Matches the paths of files holding Argo CD manifests
+pub(crate) fn manifest_globs(ctx: &Context) -> Result<GlobSet> {
+    let mut globs = GlobSetBuilder::new();
+    for glob in &ctx.config.argocd.manifests {
+        globs.add(Glob::new(glob).context(format!("parsing manifest glob {glob}"))?);
+    }
+    Ok(globs.build()?)
+}
+
+/// An annotation of a component that doesn't match the Argo CD apps
+pub(crate) struct Drift {
+    pub annotation: &'static str,
+    pub current: Option<String>,
+    pub expected: Option<String>,
+}
+
+impl Drift {
+    /// Sets the annotation to the value expected from the apps
+    pub fn fix(&self, component: &mut Component) {
+        let annotations = &mut component.metadata.annotations;
+
+        match &self.expected {
+            Some(value) => annotations.insert(self.annotation.to_owned(), value.to_owned()),
+            None => annotations.remove(self.annotation),
+        };
+    }
+}
+
+fn app_annotations(app: &ArgoApp) -> BTreeMap<&'static str, Option<String>> {
+    let name = &app.metadata.name;
+    let mut annotations = BTreeMap::from([
+        (APP_NAME, Some(name.to_owned())),
+        (LABEL_SELECTOR, Some(format!("app.kubernetes.io/instance={name}"))),
+    ]);
+
+    // apps deploying to the namespace in their manifests leave the annotation alone
+    if let Some(namespace) = &app.spec.destination.namespace {
+        annotations.insert(NAMESPACE, Some(namespace.to_owned()));
+    }
+
+    annotations
+}
+
+/// Adds the annotations the component doesn't have yet, so values set by hand are kept
+fn insert_missing(component: &mut Component, annotations: BTreeMap<&'static str, Option<String>>) {
+    for (key, value) in annotations {
+        if let Some(value) = value {
+            component
+                .metadata
+                .annotations
+                .entry(key.to_owned())
+                .or_insert(value);
+        }
+    }
+}
+
+fn annotate_app(component: &mut Component, app: &ArgoApp) {
+    insert_missing(component, app_annotations(app));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(name: &str, namespace: &str) -> ArgoApp {
+        serde_yaml::from_str(&format!(
+            "apiVersion: argoproj.io/v1alpha1\nmetadata:\n  name: {name}\n\
+             spec:\n  destination:\n    namespace: {namespace}\n"
+        ))
+        .unwrap()
+    }
+
+    fn component(annotations: &[(&str, &str)]) -> Component {
+        let mut component = Component::new("web", "", ComponentDefaults::default());
+        for (key, value) in annotations {
+            component
+                .metadata
+                .annotations
+                .insert(key.to_string(), value.to_string());
+        }
+        component
+    }
+
+    #[test]
+    fn reports_and_fixes_drift_from_a_single_app() {
+        let apps = RepoApps {
+            apps: vec![app("web", "web-prod")],
+            app_sets: vec![],
+        };
+        let mut component = component(&[
+            (APP_NAME, "web-old"),
+            (APP_SELECTOR, "team=web"),
+            (NAMESPACE, "web-prod"),
+        ]);
+
+        let drift = apps.drift(&component);
+        let reported: Vec<_> = drift
+            .iter()
+            .map(|drift| (drift.annotation, drift.current.as_deref(), drift.expected.as_deref()))
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                (APP_NAME, Some("web-old"), Some("web")),
+                (APP_SELECTOR, Some("team=web"), None),
+                (LABEL_SELECTOR, None, Some("app.kubernetes.io/instance=web")),
+            ]
+        );
+
+        for drift in &drift {
+            drift.fix(&mut component);
+        }
+        assert!(apps.drift(&component).is_empty());
+        assert_eq!(component.metadata.annotations[APP_NAME], "web");
+        assert!(!component.metadata.annotations.contains_key(APP_SELECTOR));
+    }
+
+    #[test]
+    fn reports_stale_annotations_once_the_apps_are_gone() {
+        let apps = RepoApps::default();
+
+        assert!(apps.drift(&component(&[(NAMESPACE, "web")])).is_empty());
+
+        let mut component = component(&[(APP_NAME, "web"), (NAMESPACE, "web"), ("other", "kept")]);
+        let drift = apps.drift(&component);
+        let stale: Vec<_> = drift.iter().map(|drift| drift.annotation).collect();
+        assert_eq!(stale, [APP_NAME, NAMESPACE]);
+
+        for drift in &drift {
+            drift.fix(&mut component);
+        }
+        assert_eq!(component.metadata.annotations.keys().collect::<Vec<_>>(), ["other"]);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::config::ComponentDefaults;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet};
 
@@ -24,37 +26,329 @@ pub struct ComponentSpec {
     pub depends_on: BTreeSet<String>,
     #[serde(default,skip_serializing_if = "BTreeSet::is_empty")]
     pub consumes_apis: BTreeSet<String>,
+    #[serde(default,skip_serializing_if = "BTreeSet::is_empty")]
+    pub provides_apis: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subcomponent_of: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct EntiyMetadata {
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
     pub description: String,
     #[serde(default)]
     pub annotations: BTreeMap<String, String>,
     #[serde(default,skip_serializing_if = "BTreeSet::is_empty")]
-    pub tags: BTreeSet<String>
+    pub tags: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<Link>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Link {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub _type: Option<String>,
+}
+
+impl Link {
+    pub fn new(url: impl Into<String>, title: impl Into<String>, icon: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            title: Some(title.into()),
+            icon: Some(icon.into()).filter(|icon: &String| !icon.is_empty()),
+            _type: None,
+        }
+    }
 }
 
 impl Component {
-    pub fn new(name: impl Into<String>, descripton: impl Into<String>) -> Self {
+    pub fn new(
+        name: impl Into<String>,
+        descripton: impl Into<String>,
+        defaults: ComponentDefaults,
+    ) -> Self {
         Self {
             api_version: "backstage.io/v1alpha1".to_owned(),
             kind: "Component".to_owned(),
             spec: ComponentSpec {
-                _type: "service".to_owned(),
-                lifecycle: "experimental".to_owned(),
-                owner: "hipages".to_owned(),
-                system: None,
+                _type: defaults._type,
+                lifecycle: defaults.lifecycle,
+                owner: defaults.owner,
+                system: defaults.system,
                 depends_on: BTreeSet::new(),
                 consumes_apis: BTreeSet::new(),
+                provides_apis: BTreeSet::new(),
+                subcomponent_of: None,
             },
             metadata: EntiyMetadata {
                 name: name.into(),
+                namespace: None,
                 description: descripton.into(),
                 annotations: BTreeMap::new(),
-                tags: BTreeSet::new()
+                tags: BTreeSet::new(),
+                links: vec![],
             },
         }
     }
 }
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Api {
+    api_version: String,
+    pub kind: String,
+    pub metadata: EntiyMetadata,
+    pub spec: ApiSpec,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiSpec {
+    #[serde(rename = "type")]
+    pub _type: String,
+    pub lifecycle: String,
+    pub owner: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub definition: ApiDefinition,
+}
+
+/// Points backstage at the definition file instead of inlining it
+#[derive(Serialize, Deserialize)]
+pub struct ApiDefinition {
+    #[serde(rename = "$text")]
+    pub text: String,
+}
+
+impl Api {
+    /// An API provided by `component`, sharing its ownership and lifecycle
+    pub fn new(
+        name: impl Into<String>,
+        _type: impl Into<String>,
+        definition_path: &str,
+        component: &Component,
+    ) -> Self {
+        Self {
+            api_version: "backstage.io/v1alpha1".to_owned(),
+            kind: "API".to_owned(),
+            spec: ApiSpec {
+                _type: _type.into(),
+                lifecycle: component.spec.lifecycle.to_owned(),
+                owner: component.spec.owner.to_owned(),
+                system: component.spec.system.to_owned(),
+                definition: ApiDefinition {
+                    text: format!("./{definition_path}"),
+                },
+            },
+            metadata: EntiyMetadata {
+                name: name.into(),
+                namespace: component.metadata.namespace.to_owned(),
+                description: format!("{definition_path} in {}", component.metadata.name),
+                annotations: BTreeMap::new(),
+                tags: BTreeSet::new(),
+                links: vec![],
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct System {
+    api_version: String,
+    pub kind: String,
+    pub metadata: EntiyMetadata,
+    pub spec: SystemSpec,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SystemSpec {
+    pub owner: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+}
+
+impl System {
+    /// A system in the namespace, which is left out when it's the default one
+    pub fn new(namespace: &str, name: impl Into<String>, owner: impl Into<String>) -> Self {
+        Self {
+            api_version: "backstage.io/v1alpha1".to_owned(),
+            kind: "System".to_owned(),
+            spec: SystemSpec {
+                owner: owner.into(),
+                domain: None,
+            },
+            metadata: EntiyMetadata {
+                name: name.into(),
+                namespace: Some(namespace.to_owned()).filter(|namespace| namespace != "default"),
+                description: String::new(),
+                annotations: BTreeMap::new(),
+                tags: BTreeSet::new(),
+                links: vec![],
+            },
+        }
+    }
+}
+
+/// A catalog file built around a component, keeping any other entities defined alongside it
+pub struct CatalogFile {
+    pub component: Component,
+    documents: Vec<serde_yaml::Value>,
+    component_index: usize,
+}
+
+impl CatalogFile {
+    /// Parses the documents of a catalog file, leaving out empty ones such as the one after a
+    /// trailing `---`
+    pub fn parse(content: &str) -> Result<Self> {
+        let documents = serde_yaml::Deserializer::from_str(content)
+            .map(serde_yaml::Value::deserialize)
+            .filter(|document| !matches!(document, Ok(serde_yaml::Value::Null)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let component_index = documents
+            .iter()
+            .position(|document| document["kind"] == "Component")
+            .context("no Component in catalog file")?;
+
+        let component = serde_yaml::from_value(documents[component_index].clone())?;
+
+        Ok(Self {
+            component,
+            documents,
+            component_index,
+        })
+    }
+
+    /// Whether an entity of this kind and name is already defined in the file
+    pub fn contains(&self, kind: &str, name: &str) -> bool {
+        self.documents.iter().any(|document| {
+            document["kind"].as_str().is_some_and(|k| k.eq_ignore_ascii_case(kind))
+                && document["metadata"]["name"] == name
+        })
+    }
+
+    pub fn push(&mut self, entity: &impl Serialize) -> Result<()> {
+        self.documents.push(serde_yaml::to_value(entity)?);
+        Ok(())
+    }
+
+    pub fn to_yaml(&self) -> Result<String> {
+        let component = serde_yaml::to_string(&self.component)?;
+
+        if self.documents.len() == 1 {
+            return Ok(component);
+        }
+
+        let documents = self
+            .documents
+            .iter()
+            .enumerate()
+            .map(|(idx, document)| {
+                if idx == self.component_index {
+                    Ok(component.to_owned())
+                } else {
+                    serde_yaml::to_string(document)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(documents.join("---\n"))
+    }
+}
+
+/// A catalog entity of any kind, with the spec left untyped so relations can be read generically
+#[derive(Deserialize)]
+pub struct Entity {
+    pub kind: String,
+    pub metadata: EntityName,
+    #[serde(default)]
+    pub spec: BTreeMap<String, serde_yaml::Value>,
+}
+
+#[derive(Deserialize)]
+pub struct EntityName {
+    pub name: String,
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+impl Entity {
+    /// The namespace of this entity, `default` when it doesn't set one
+    pub fn namespace(&self) -> &str {
+        self.metadata.namespace.as_deref().unwrap_or("default")
+    }
+
+    /// The fully qualified ref of this entity, eg `component:default/my-service`
+    pub fn entity_ref(&self) -> String {
+        format!("{}:{}/{}", self.kind, self.namespace(), self.metadata.name).to_lowercase()
+    }
+}
+
+/// Parses every document in a catalog file, skipping documents that are not entities
+pub fn parse_entities(content: &str) -> Vec<Entity> {
+    serde_yaml::Deserializer::from_str(content)
+        .filter_map(|document| Entity::deserialize(document).ok())
+        .collect()
+}
+
+/// Expands a possibly shorthand entity ref (`my-service`, `component:my-service`) to
+/// `kind:namespace/name`, using `default_kind` when the ref has no kind. Like Backstage, refs
+/// without a namespace are in the namespace of the entity referencing them.
+pub fn full_entity_ref(reference: &str, default_kind: &str, default_namespace: &str) -> String {
+    let (kind, rest) = reference
+        .split_once(':')
+        .unwrap_or((default_kind, reference));
+    let (namespace, name) = rest.split_once('/').unwrap_or((default_namespace, rest));
+
+    format!("{kind}:{namespace}/{name}").to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CATALOG: &str = "\
+apiVersion: backstage.io/v1alpha1
+kind: API
+metadata:
+  name: web-api
+spec:
+  type: openapi
+---
+apiVersion: backstage.io/v1alpha1
+kind: Component
+metadata:
+  name: web
+  description: The website
+spec:
+  type: service
+  lifecycle: production
+  owner: group:web
+  providesApis: [web-api]
+---
+";
+
+    #[test]
+    fn parses_component_of_multi_document_file() {
+        let catalog = CatalogFile::parse(CATALOG).unwrap();
+
+        assert_eq!(catalog.component.metadata.name, "web");
+        assert!(catalog.contains("api", "web-api"));
+        assert!(!catalog.contains("component", "web-api"));
+        assert!(CatalogFile::parse("kind: API\nmetadata:\n  name: web-api\n").is_err());
+    }
+
+    #[test]
+    fn expands_entity_refs() {
+        assert_eq!(full_entity_ref("Web", "component", "default"), "component:default/web");
+        assert_eq!(full_entity_ref("api:web-api", "component", "payments"), "api:payments/web-api");
+        assert_eq!(full_entity_ref("system:shop/cart", "system", "payments"), "system:shop/cart");
+    }
+}
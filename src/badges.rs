@@ -0,0 +1,359 @@
+use crate::{
+    backstage::Component,
+    config::{BadgeTemplate, BadgesConfig},
+    readme::{self, Markup},
+    template::{self, Vars},
+};
+use octocrab::models::Repository;
+use regex::Regex;
+
+/// A badge image, optionally linking somewhere
+pub(crate) struct Badge {
+    pub alt: String,
+    pub image: String,
+    pub title: Option<String>,
+    pub link: Option<String>,
+}
+
+impl Badge {
+    /// The badge in the given markup, reStructuredText badges take several lines
+    pub fn to_markup(&self, markup: Markup) -> String {
+        match markup {
+            Markup::Markdown => {
+                let image = match &self.title {
+                    Some(title) => format!("![{}]({} \"{}\")", self.alt, self.image, title),
+                    None => format!("![{}]({})", self.alt, self.image),
+                };
+
+                match &self.link {
+                    Some(link) => format!("[{image}]({link})"),
+                    None => image,
+                }
+            }
+            Markup::ReStructuredText => {
+                let mut directive = format!(".. image:: {}\n   :alt: {}", self.image, self.alt);
+                if let Some(link) = &self.link {
+                    directive.push_str(&format!("\n   :target: {link}"));
+                }
+                directive
+            }
+            Markup::AsciiDoc => {
+                let mut attributes = vec![format!("\"{}\"", self.alt.replace('"', "'"))];
+                if let Some(link) = &self.link {
+                    attributes.push(format!("link={link}"));
+                }
+                if let Some(title) = &self.title {
+                    attributes.push(format!("title=\"{}\"", title.replace('"', "'")));
+                }
+                format!("image:{}[{}]", self.image, attributes.join(","))
+            }
+            Markup::PlainText => format!(
+                "{}: {}",
+                self.alt,
+                self.link.as_ref().unwrap_or(&self.image)
+            ),
+        }
+    }
+}
+
+/// Renders the configured badges for an entity, leaving out badges using a variable it doesn't
+/// have, eg a system badge for an entity without a system
+pub(crate) fn badges(component: &Component, repo: &Repository, config: &BadgesConfig) -> Vec<Badge> {
+    let vars = badge_vars(component, repo, config);
+
+    config
+        .badges
+        .iter()
+        .filter_map(|badge| render(badge, &vars))
+        .collect()
+}
+
+fn render(badge: &BadgeTemplate, vars: &Vars) -> Option<Badge> {
+    let optional = |field: &Option<String>| match field {
+        Some(field) => template::render(field, vars).map(Some),
+        None => Some(None),
+    };
+
+    Some(Badge {
+        alt: template::render(&badge.alt, vars)?,
+        image: template::render(&badge.image, vars)?,
+        title: optional(&badge.title)?,
+        link: optional(&badge.link)?,
+    })
+}
+
+fn badge_vars(component: &Component, repo: &Repository, config: &BadgesConfig) -> Vars {
+    let mut vars = template::entity_vars(component, repo);
+    vars.insert("portal_url", config.portal_url.trim_end_matches('/').to_owned());
+    vars
+}
+
+const BLOCK_MARKER: &str = "landscaper:badges";
+
+/// Matches a single badge, which spans several lines in reStructuredText
+fn badge_regex(markup: Markup) -> Regex {
+    Regex::new(match markup {
+        Markup::Markdown => r"\[!\[[^\]]*\]\([^)]*\)\]\([^)]*\)|!\[[^\]]*\]\([^)]*\)",
+        Markup::ReStructuredText => r"(?m)^\.\. (\|[^|]+\| )?image:: \S+(\n[ \t]+\S.*)*",
+        Markup::AsciiDoc => r"image::?[^\s\[]+\[[^\]]*\]",
+        Markup::PlainText => r"$^",
+    })
+    .unwrap()
+}
+
+/// The image url of a badge
+fn badge_image(markup: Markup, badge: &str) -> Option<&str> {
+    let re = Regex::new(match markup {
+        Markup::Markdown => r"!\[[^\]]*\]\(\s*([^)\s]+)",
+        Markup::ReStructuredText => r"image:: (\S+)",
+        Markup::AsciiDoc => r"image::?([^\s\[]+)\[",
+        Markup::PlainText => r"(\S+://\S+)",
+    })
+    .unwrap();
+
+    re.captures(badge)
+        .and_then(|captures| captures.get(1))
+        .map(|image| image.as_str())
+}
+
+/// The badges making up a line, or None if the line has anything else on it
+fn line_badges<'a>(re: &Regex, line: &'a str) -> Option<Vec<&'a str>> {
+    let badges: Vec<_> = re.find_iter(line).map(|badge| badge.as_str()).collect();
+
+    (!badges.is_empty() && re.replace_all(line, "").trim().is_empty()).then_some(badges)
+}
+
+/// Index of the first line after any YAML front matter
+fn body_start(markup: Markup, lines: &[&str]) -> usize {
+    match markup {
+        Markup::Markdown => readme::front_matter_len(lines),
+        _ => 0,
+    }
+}
+
+/// The comment lines the managed block starts and ends with
+fn block_markers(markup: Markup) -> Option<(String, String)> {
+    Some((
+        markup.comment(BLOCK_MARKER)?,
+        markup.comment(&format!("/{BLOCK_MARKER}"))?,
+    ))
+}
+
+/// Takes the managed block out of the lines, returning whether there was one
+fn take_block(markup: Markup, lines: &mut Vec<&str>) -> bool {
+    let Some((start_marker, end_marker)) = block_markers(markup) else {
+        return false;
+    };
+    let Some(start) = lines.iter().position(|line| line.trim() == start_marker) else {
+        return false;
+    };
+    let Some(end) = lines[start..].iter().position(|line| line.trim() == end_marker) else {
+        return false;
+    };
+
+    lines.drain(start..=start + end);
+    if lines.get(start).is_some_and(|line| line.trim().is_empty()) {
+        lines.remove(start);
+    } else if start == lines.len() && lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+
+    true
+}
+
+/// Takes the badge only lines around the first heading out of the lines, so they can be moved
+/// into the managed block. reStructuredText badges are left alone as they span several lines.
+fn take_loose_badges(markup: Markup, lines: &mut Vec<&str>) -> Vec<String> {
+    if markup == Markup::ReStructuredText {
+        return vec![];
+    }
+
+    let re = badge_regex(markup);
+    let mut badges = vec![];
+    let mut seen_heading = false;
+    let mut idx = body_start(markup, lines);
+
+    while idx < lines.len() {
+        if let Some(line_badges) = line_badges(&re, lines[idx]) {
+            badges.extend(line_badges.into_iter().map(str::to_owned));
+            lines.remove(idx);
+        } else if lines[idx].trim().is_empty() {
+            idx += 1;
+        } else if let Some(heading) = markup.heading_at(lines, idx).filter(|_| !seen_heading) {
+            seen_heading = true;
+            idx += heading;
+        } else {
+            break;
+        }
+    }
+
+    badges
+}
+
+/// Index of the line after the first heading, or the start of the body without one
+fn block_position(markup: Markup, lines: &[&str]) -> usize {
+    let start = body_start(markup, lines);
+
+    (start..lines.len())
+        .find_map(|idx| markup.heading_at(lines, idx).map(|heading| idx + heading))
+        .unwrap_or(start)
+}
+
+/// Puts the badges in the managed block after the first heading, replacing the block's previous
+/// badges. Badges on their own lines around the heading are moved into the block after the given
+/// ones, unless they show the same image. Plain text READMEs can't hold a block and are returned
+/// as is.
+pub(crate) fn update_block(readme: &str, markup: Markup, badges: &[Badge]) -> String {
+    let Some((start_marker, end_marker)) = block_markers(markup) else {
+        return readme.to_owned();
+    };
+
+    let mut lines: Vec<_> = readme.lines().collect();
+    take_block(markup, &mut lines);
+    let loose = take_loose_badges(markup, &mut lines);
+
+    let mut block: Vec<String> = badges.iter().map(|badge| badge.to_markup(markup)).collect();
+    for badge in loose {
+        if !block
+            .iter()
+            .any(|other| badge_image(markup, other) == badge_image(markup, &badge))
+        {
+            block.push(badge);
+        }
+    }
+
+    let position = block_position(markup, &lines);
+    while lines.get(position).is_some_and(|line| line.trim().is_empty()) {
+        lines.remove(position);
+    }
+
+    // reStructuredText directives and comments need blank lines between them
+    let separator = match markup {
+        Markup::ReStructuredText => "\n\n",
+        _ => "\n",
+    };
+
+    let mut inserted = vec![];
+    if position > 0 {
+        inserted.push(String::new());
+    }
+    inserted.push(
+        std::iter::once(start_marker)
+            .chain(block)
+            .chain(std::iter::once(end_marker))
+            .collect::<Vec<_>>()
+            .join(separator),
+    );
+    if position < lines.len() {
+        inserted.push(String::new());
+    }
+
+    let lines: Vec<String> = lines[..position]
+        .iter()
+        .map(|line| line.to_string())
+        .chain(inserted)
+        .chain(lines[position..].iter().map(|line| line.to_string()))
+        .collect();
+
+    readme::with_line_endings(readme, lines.join("\n") + "\n")
+}
+
+/// Takes the managed block out of the readme, leaving any other badges alone
+pub(crate) fn remove_block(readme: &str, markup: Markup) -> String {
+    let mut lines: Vec<_> = readme.lines().collect();
+
+    if !take_block(markup, &mut lines) {
+        return readme.to_owned();
+    }
+
+    readme::with_line_endings(readme, lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn badge(alt: &str, image: &str) -> Badge {
+        Badge {
+            alt: alt.to_owned(),
+            image: image.to_owned(),
+            title: None,
+            link: None,
+        }
+    }
+
+    #[test]
+    fn adds_block_after_heading() {
+        let readme = "# Title\n\nSome text\n";
+        let updated = update_block(readme, Markup::Markdown, &[badge("CI", "https://ci/b.svg")]);
+
+        assert_eq!(
+            updated,
+            "# Title\n\n<!-- landscaper:badges -->\n![CI](https://ci/b.svg)\n\
+             <!-- /landscaper:badges -->\n\nSome text\n"
+        );
+    }
+
+    #[test]
+    fn rebuilds_block_from_config() {
+        let readme = "# Title\n\n<!-- landscaper:badges -->\n![Old](https://old/b.svg)\n\
+                      <!-- /landscaper:badges -->\n\nSome text\n";
+        let updated = update_block(readme, Markup::Markdown, &[badge("CI", "https://ci/b.svg")]);
+
+        assert_eq!(
+            updated,
+            "# Title\n\n<!-- landscaper:badges -->\n![CI](https://ci/b.svg)\n\
+             <!-- /landscaper:badges -->\n\nSome text\n"
+        );
+        assert_eq!(
+            update_block(&updated, Markup::Markdown, &[badge("CI", "https://ci/b.svg")]),
+            updated
+        );
+    }
+
+    #[test]
+    fn moves_loose_badges_into_block() {
+        let readme = "# Title\n![CI](https://ci/b.svg) ![Cov](https://cov/b.svg)\n\nSome text\n";
+        let updated = update_block(readme, Markup::Markdown, &[badge("Build", "https://ci/b.svg")]);
+
+        assert_eq!(
+            updated,
+            "# Title\n\n<!-- landscaper:badges -->\n![Build](https://ci/b.svg)\n\
+             ![Cov](https://cov/b.svg)\n<!-- /landscaper:badges -->\n\nSome text\n"
+        );
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        let readme = "# Title\r\n\r\nSome text\r\n";
+        let updated = update_block(readme, Markup::Markdown, &[badge("CI", "https://ci/b.svg")]);
+
+        assert_eq!(
+            updated,
+            "# Title\r\n\r\n<!-- landscaper:badges -->\r\n![CI](https://ci/b.svg)\r\n\
+             <!-- /landscaper:badges -->\r\n\r\nSome text\r\n"
+        );
+        assert_eq!(remove_block(&updated, Markup::Markdown), readme);
+    }
+
+    #[test]
+    fn removes_only_the_block() {
+        let readme = "# Title\n\n<!-- landscaper:badges -->\n![CI](https://ci/b.svg)\n\
+                      <!-- /landscaper:badges -->\n\n![Cov](https://cov/b.svg)\n";
+
+        assert_eq!(
+            remove_block(readme, Markup::Markdown),
+            "# Title\n\n![Cov](https://cov/b.svg)\n"
+        );
+        assert_eq!(remove_block("# Title\n", Markup::Markdown), "# Title\n");
+    }
+
+    #[test]
+    fn leaves_plain_text_alone() {
+        let readme = "Title\n";
+        assert_eq!(
+            update_block(readme, Markup::PlainText, &[badge("CI", "https://ci/b.svg")]),
+            readme
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use crate::config::CacheConfig;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use http::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    HeaderMap, HeaderValue,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{DirBuilder, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+/// GitHub API responses kept on disk between runs, keyed by url. Entries older than the ttl are
+/// revalidated with a conditional request, which doesn't count against the rate limit when the
+/// response is unchanged. Responses can hold the contents of private repos, so only the user can
+/// read the cache.
+pub(crate) struct HttpCache {
+    dir: PathBuf,
+    ttl_secs: i64,
+    max_age: Duration,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Entry {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Unix timestamp of when the response was last fetched or revalidated
+    fetched_at: i64,
+    pub body: String,
+}
+
+impl HttpCache {
+    /// The cache set up in the config, None when no cache dir is set or can be found
+    pub fn from_config(config: &CacheConfig) -> Option<Self> {
+        let dir = config
+            .dir
+            .clone()
+            .or_else(|| dirs::cache_dir().map(|dir| dir.join("landscaper")))?;
+
+        Some(Self {
+            dir,
+            ttl_secs: config.ttl_secs as i64,
+            max_age: Duration::from_secs(config.max_age_days * 24 * 60 * 60),
+        })
+    }
+
+    pub fn get(&self, url: &str) -> Option<Entry> {
+        let content = std::fs::read_to_string(self.path(url)).ok()?;
+        serde_json::from_str::<Entry>(&content)
+            .ok()
+            .filter(|entry| entry.url == url)
+    }
+
+    pub fn put(&self, entry: &Entry) -> Result<()> {
+        let mut dir = DirBuilder::new();
+        dir.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut dir, 0o700);
+        dir.create(&self.dir)
+            .context(format!("creating cache dir {}", self.dir.display()))?;
+
+        let path = self.path(&entry.url);
+        let mut file = OpenOptions::new();
+        file.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+
+        let content = serde_json::to_string(entry)?;
+        file.open(&path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .context(format!("writing cache entry {}", path.display()))
+    }
+
+    /// Deletes the entries that haven't been fetched or revalidated for longer than the max age,
+    /// returning how many were deleted
+    pub fn prune(&self) -> Result<usize> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Ok(0);
+        };
+
+        let mut pruned = 0;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") || !path.is_file() {
+                continue;
+            }
+
+            let modified = std::fs::metadata(&path)?.modified()?;
+
+            if SystemTime::now().duration_since(modified).unwrap_or_default() > self.max_age {
+                std::fs::remove_file(&path)
+                    .context(format!("deleting cache entry {}", path.display()))?;
+                pruned += 1;
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    /// Whether the entry is recent enough to use without asking GitHub
+    pub fn is_fresh(&self, entry: &Entry) -> bool {
+        Utc::now().timestamp() - entry.fetched_at < self.ttl_secs
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}.json", hex::encode(Sha256::digest(url))))
+    }
+}
+
+impl Entry {
+    pub fn new(url: &str, headers: &HeaderMap, body: String) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(str::to_owned)
+        };
+
+        Self {
+            url: url.to_owned(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            fetched_at: Utc::now().timestamp(),
+            body,
+        }
+    }
+
+    /// Headers asking GitHub to only send the response if it changed since this entry was fetched
+    pub fn conditional_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        if let Some(etag) = self.etag.as_deref().and_then(|etag| etag.parse().ok()) {
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+        if let Some(date) = self.last_modified.as_deref().and_then(|date| date.parse().ok()) {
+            headers.insert(IF_MODIFIED_SINCE, date);
+        }
+
+        headers
+    }
+
+    /// Marks the entry as fetched now, after GitHub said it's unchanged
+    pub fn touch(&mut self) {
+        self.fetched_at = Utc::now().timestamp();
+    }
+}
@@ -1,4 +1,5 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
 
 /// Super cool backyard tools
 #[derive(Debug, Parser)]
@@ -11,20 +12,22 @@ pub(crate) struct App {
     pub command: Command,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 pub(crate) struct GlobalOpts {
     /// Flag to actually write the changes to github
     #[arg(short, long, default_value_t = false, global = true)]
     pub write: bool,
 
-    /// The github org to search
-    #[arg()]
-    pub org: String,
+    /// The orgs or users whose repos to work on, separated by commas. Defaults to the owners of
+    /// the profile
+    #[arg(action = ArgAction::Set, num_args = 1, value_delimiter = ',')]
+    pub owners: Vec<String>,
 
-    /// The branch changes will be pushes to
-    #[arg(short, long, default_value = "landscaper", global = true)]
-    pub branch: String,
+    /// The branch changes will be pushes to [default: {branch_prefix}/{command} with a
+    /// branch_prefix in the config, else landscaper]
+    #[arg(short, long, global = true)]
+    pub branch: Option<String>,
 
     /// Regex filter on the repository name, use this to only target specific repositories
     #[arg(long, global = true)]
@@ -32,7 +35,28 @@ pub(crate) struct GlobalOpts {
 
     /// Skip the first n repos when processing
     #[arg(long, global = true)]
-    pub skip: Option<usize>
+    pub skip: Option<usize>,
+
+    /// Path to a landscaper.toml config file, looked for in the current dir and the user's config
+    /// dir when not given
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Name of the campaign the PRs are part of, available to PR templates as `{campaign}`
+    #[arg(long, global = true)]
+    pub campaign: Option<String>,
+
+    /// Profile from the config file to use
+    #[arg(short, long, global = true)]
+    pub profile: Option<String>,
+
+    /// Url of the GitHub Enterprise Server the owners are on, eg https://github.example.com
+    #[arg(long, global = true)]
+    pub github_url: Option<String>,
+
+    /// Don't read or write the on-disk cache of GitHub responses
+    #[arg(long, default_value_t = false, global = true)]
+    pub no_cache: bool,
 }
 
 #[derive(Debug, Subcommand)]
@@ -42,9 +66,19 @@ pub(crate) enum Command {
     /// Create missing catalog-info.yaml files in an org
     CreateCatalogFiles {},
     /// Try and fill out catalog-info.yaml files in an org
-    EnrichCatalogFiles {},
+    EnrichCatalogFiles(EnrichArgs),
     /// Add backstage badges to README.md files in an org
-    AddBadgesToReadmes {},
+    AddBadgesToReadmes(BadgesArgs),
+    /// Build the dependency graph of the catalog entities in an org
+    Graph(GraphArgs),
+    /// Create System entities for systems that are referenced but not defined
+    CreateSystemEntities(CreateSystemsArgs),
+    /// Change fields of every Argo CD application in an org
+    ArgocdSet(ArgocdSetArgs),
+    /// Check every Argo CD application in an org against a policy
+    ArgocdLint(ArgocdLintArgs),
+    /// Add, update or remove a section of the README in every repo in an org
+    ReadmeSection(ReadmeSectionArgs),
 }
 
 #[derive(Debug, Args)]
@@ -61,3 +95,243 @@ pub(crate) struct FindReplaceArgs {
     #[arg(short, long)]
     pub message: Option<String>,
 }
+
+#[derive(Debug, Args)]
+pub(crate) struct EnrichArgs {
+    /// YAML or TOML file with the rules used to detect dependencies
+    #[arg(long)]
+    pub rules: Option<PathBuf>,
+
+    /// Download each repo and check the rules against its files instead of using code search
+    #[arg(long, default_value_t = false)]
+    pub local: bool,
+
+    /// Add API entities for OpenAPI, AsyncAPI, GraphQL and protobuf files found in each repo
+    #[arg(long, default_value_t = false)]
+    pub apis: bool,
+
+    /// Add the techdocs-ref annotation to repos with a mkdocs.yml and docs folder
+    #[arg(long, default_value_t = false)]
+    pub techdocs: bool,
+
+    /// Tag entries with the languages, frameworks and runtimes used in each repo
+    #[arg(long, default_value_t = false)]
+    pub tags: bool,
+
+    /// Add links to the homepage, GitHub Pages, Argo CD and configured link templates
+    #[arg(long, default_value_t = false)]
+    pub links: bool,
+
+    /// Set the system of entries without one from the mapping file, catalog rules or Argo CD project
+    #[arg(long, default_value_t = false)]
+    pub systems: bool,
+
+    /// Create a mkdocs.yml and docs/index.md from the README in repos without TechDocs
+    #[arg(long, default_value_t = false, requires = "techdocs")]
+    pub techdocs_scaffold: bool,
+
+    /// Compare the Kubernetes annotations of entries with their Argo CD apps, and report or fix
+    /// the ones that drifted
+    #[arg(long, value_enum)]
+    pub argocd_drift: Option<DriftMode>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum DriftMode {
+    /// Print the annotations that don't match
+    Report,
+    /// Update or remove the annotations that don't match
+    Fix,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct CreateSystemsArgs {
+    /// Repo holding the central catalog, where the System entities are created, as `owner/repo`
+    /// or just `repo` when it belongs to the first owner
+    #[arg(long)]
+    pub catalog_repo: String,
+
+    /// Directory in the catalog repo for System entity files
+    #[arg(long, default_value = "systems")]
+    pub path: String,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct BadgesArgs {
+    /// Remove the managed badge block instead of updating it
+    #[arg(long, default_value_t = false)]
+    pub remove: bool,
+
+    /// Create a README.md from the catalog entry in repos without a README
+    #[arg(long, default_value_t = false, conflicts_with = "remove")]
+    pub create_readme: bool,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct GraphArgs {
+    /// Output format of the graph
+    #[arg(short, long, value_enum, default_value_t = GraphFormat::Dot)]
+    pub format: GraphFormat,
+
+    /// Only output the part of the graph connected to this entity, eg component:default/my-service
+    #[arg(long)]
+    pub focus: Option<String>,
+
+    /// Which relations of the focused entity to follow
+    #[arg(long, value_enum, default_value_t = GraphDirection::Both, requires = "focus")]
+    pub direction: GraphDirection,
+
+    /// Highlight and report references to entities that are not defined in any catalog file
+    #[arg(long, default_value_t = false)]
+    pub dangling: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum GraphFormat {
+    Dot,
+    Mermaid,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum GraphDirection {
+    /// Entities the focused entity depends on
+    Upstream,
+    /// Entities that depend on the focused entity
+    Downstream,
+    Both,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ArgocdSetArgs {
+    /// Set syncPolicy.automated.prune
+    #[arg(long)]
+    pub prune: Option<bool>,
+
+    /// Set syncPolicy.automated.selfHeal
+    #[arg(long)]
+    pub self_heal: Option<bool>,
+
+    /// Add a sync option, eg CreateNamespace=true
+    #[arg(long)]
+    pub sync_option: Vec<String>,
+
+    /// Move destinations to this cluster server
+    #[arg(long)]
+    pub server: Option<String>,
+
+    /// Only move destinations currently on this cluster server
+    #[arg(long, requires = "server")]
+    pub from_server: Option<String>,
+
+    /// Rename a destination namespace, as OLD=NEW
+    #[arg(long, value_parser = parse_key_value)]
+    pub rename_namespace: Vec<(String, String)>,
+
+    /// Add a finalizer, eg resources-finalizer.argocd.argoproj.io
+    #[arg(long)]
+    pub finalizer: Vec<String>,
+
+    /// Set a label, as KEY=VALUE
+    #[arg(long, value_parser = parse_key_value)]
+    pub label: Vec<(String, String)>,
+
+    /// Title of the pull requests
+    #[arg(short, long)]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ArgocdLintArgs {
+    /// YAML or TOML file with the policy to check applications against
+    #[arg(long)]
+    pub policy: PathBuf,
+
+    /// Open PRs fixing the findings that can be fixed automatically
+    #[arg(long, default_value_t = false)]
+    pub fix: bool,
+
+    /// Write the findings to this file as JSON
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ReadmeSectionArgs {
+    /// Heading of the section, matched case insensitively
+    #[arg(long, required_unless_present = "marker")]
+    pub heading: Option<String>,
+
+    /// Level of the heading when the section is added
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(1..=6))]
+    pub level: u8,
+
+    /// Find the section between `<!-- landscaper:MARKER -->` comments instead of by heading
+    #[arg(long, conflicts_with = "heading")]
+    pub marker: Option<String>,
+
+    /// Content of the section, can use the {name}, {kind}, {description}, {owner}, {lifecycle},
+    /// {namespace}, {system}, {full_name} and {default_branch} variables
+    #[arg(long, required_unless_present_any = ["content_file", "remove"])]
+    pub content: Option<String>,
+
+    /// File to read the content of the section from
+    #[arg(long, conflicts_with = "content")]
+    pub content_file: Option<PathBuf>,
+
+    /// Heading of the section to add the section after, it goes at the end otherwise
+    #[arg(long)]
+    pub after: Option<String>,
+
+    /// Remove the section instead of updating it
+    #[arg(long, default_value_t = false, conflicts_with_all = ["content", "content_file"])]
+    pub remove: bool,
+
+    /// Commit message to create the pull requests with
+    #[arg(short, long)]
+    pub message: Option<String>,
+}
+
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> App {
+        App::try_parse_from(std::iter::once("landscaper").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn parses_owners_before_the_command() {
+        let app = parse(&["acme,acme-labs", "create-catalog-files", "--write"]);
+
+        assert_eq!(app.global_opts.owners, ["acme", "acme-labs"]);
+        assert!(app.global_opts.write);
+        assert!(matches!(app.command, Command::CreateCatalogFiles {}));
+    }
+
+    #[test]
+    fn parses_command_without_owners() {
+        let app = parse(&["--profile", "payments", "graph", "--dangling"]);
+
+        assert!(app.global_opts.owners.is_empty());
+        assert!(matches!(app.command, Command::Graph(GraphArgs { dangling: true, .. })));
+    }
+
+    #[test]
+    fn parses_command_args() {
+        let app = parse(&["my-org", "argocd-set", "--label", "team=web", "--prune", "true"]);
+
+        let Command::ArgocdSet(args) = app.command else {
+            panic!("expected argocd-set");
+        };
+        assert_eq!(args.label, [("team".to_owned(), "web".to_owned())]);
+        assert_eq!(args.prune, Some(true));
+    }
+}
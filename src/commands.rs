@@ -1,9 +1,19 @@
 mod create_catalog_entries;
+mod create_system_entities;
 mod enrich_catalog_entries;
 mod find_and_replace;
 mod add_badges_to_readme;
+mod argocd_lint;
+mod argocd_set;
+mod graph;
+mod readme_section;
 
 pub(crate) use find_and_replace::find_and_replace_in_org;
 pub(crate) use create_catalog_entries::create_missing_catalog_files;
+pub(crate) use create_system_entities::create_missing_system_entities;
 pub(crate) use enrich_catalog_entries::enrich_catalog_files;
-pub(crate) use add_badges_to_readme::add_badges_to_readme;
\ No newline at end of file
+pub(crate) use add_badges_to_readme::add_badges_to_readme;
+pub(crate) use argocd_lint::argocd_lint;
+pub(crate) use argocd_set::argocd_set;
+pub(crate) use graph::dependency_graph;
+pub(crate) use readme_section::readme_section;
\ No newline at end of file
@@ -1,58 +1,48 @@
-use crate::{apply_changes, backstage, Change, ChangeSet, Context, Output};
+use crate::{
+    apply_changes, backstage, badges,
+    cli::BadgesArgs,
+    find_repos,
+    pull_request::Message,
+    readme::{self, Markup},
+    repo_files::{Blob, RepoBlobs},
+    repo_owner, Change, ChangeSet, Context, Output,
+};
 use anyhow::{Context as anyhowContext, Result};
 use log::{debug, info, warn};
-use octocrab::{models::Repository, params::Direction};
+use octocrab::models::Repository;
 use prettydiff::{diff_lines, text::ContextConfig};
-use regex::Regex;
-
-pub(crate) async fn add_badges_to_readme(ctx: &Context) -> Result<()> {
-    let owner = &ctx.options.org;
-    let repos = ctx
-        .client
-        .orgs(owner)
-        .list_repos()
-        .direction(Direction::Descending)
-        .sort(octocrab::params::repos::Sort::Updated)
-        .send()
-        .await?;
 
+pub(crate) async fn add_badges_to_readme(ctx: &Context, args: &BadgesArgs) -> Result<()> {
     let mut results = vec![];
 
     info!("Finding eligable repos...");
 
     let skip = ctx.options.skip.unwrap_or(0);
-    let repos: Vec<_> = ctx
-        .client
-        .all_pages(repos)
-        .await?
-        .into_iter()
-        .filter(|repo| {
-            if let Some(true) = repo.archived {
-                return false;
-            }
-
-            if let Some(filter) = &ctx.options.repo {
-                let re = Regex::new(filter).unwrap();
-                if !re.is_match(&repo.name) {
-                    return false;
-                }
-            }
-            true
-        })
-        .skip(skip)
-        .collect();
+    let repos = find_repos(ctx).await?;
+    let fetched = readme::fetch_readmes(&ctx.client, &repos, &["catalog-info.yaml"]).await?;
 
-    info!("{} found repos to process", repos.len());
     let total_repos = repos.len() + skip;
-    for (idx, repo) in repos.into_iter().enumerate() {
-        if (idx + skip) % 10 == 0 {
+    for (idx, (repo, fetched)) in repos.into_iter().zip(fetched).enumerate() {
+        if (idx + skip).is_multiple_of(10) {
             info!("{}/{total_repos} repos processed", idx+skip);
         }
 
-        let changeset = add_badge_to_readme(ctx, &repo).await.with_context(||format!(
-            "updating catalog-info.yaml for {}",
-            repo.html_url.clone().unwrap().as_str()
-        ))?;
+        let (readme_path, blobs) = match fetched {
+            Ok(fetched) => fetched,
+            Err(err) => {
+                warn!("skipping {}: {err:#}", repo.name);
+                continue;
+            }
+        };
+
+        let changeset = match add_badge_to_readme(ctx, &repo, readme_path.as_deref(), &blobs, args)
+        {
+            Ok(changeset) => changeset,
+            Err(err) => {
+                warn!("skipping {}, its README can't be updated: {err:#}", repo.name);
+                continue;
+            }
+        };
 
         if changeset.changes.is_empty() {
             // info!("no changes for {}", repo.name);
@@ -64,7 +54,11 @@ pub(crate) async fn add_badges_to_readme(ctx: &Context) -> Result<()> {
                 ctx,
                 &repo,
                 changeset,
-                "[ci-skip] docs: Add ownership badges to readme".to_owned(),
+                if args.remove {
+                    Message::new("docs", "Remove badges from readme").skip_ci_prefix("[ci-skip]")
+                } else {
+                    Message::new("docs", "Update badges in readme").skip_ci_prefix("[ci-skip]")
+                },
             )
             .await
             .context(format!("creating PR for {}", repo.name))?,
@@ -82,80 +76,90 @@ pub(crate) async fn add_badges_to_readme(ctx: &Context) -> Result<()> {
     Ok(())
 }
 
-async fn add_badge_to_readme(ctx: &Context, repo: &Repository) -> Result<ChangeSet> {
-    let owner = &ctx.options.org;
-
-    let readme = ctx
-        .client
-        .get_file_content(owner, &repo.name, "README.md")
-        .await
-        .context(format!("getting README.md for {}/{}", owner, repo.name))?;
-
-    let readme_content = readme.decoded_content().context(format!(
-        "getting content for README.md for {}/{}",
-        owner, repo.name
-    ))?;
-
-    if readme_content.contains("https://backyard.k8s.hipages.com.au/api/badges/entity/") {
-        debug!("{} already has a badge, skipping", &repo.name);
+fn add_badge_to_readme(
+    ctx: &Context,
+    repo: &Repository,
+    readme_path: Option<&str>,
+    blobs: &RepoBlobs,
+    args: &BadgesArgs,
+) -> Result<ChangeSet> {
+    let owner = repo_owner(repo)?;
+
+    let readme = match readme_path {
+        Some(path) => Some((
+            path,
+            blobs
+                .get(path)
+                .context(format!("getting {} for {}/{}", path, owner, repo.name))?,
+        )),
+        None => None,
+    };
+
+    let markup = readme.map_or(Markup::Markdown, |(path, _)| Markup::from_path(path));
+
+    if markup == Markup::PlainText {
+        info!("{} has a plain text README, badges can't be added", &repo.name);
         return Ok(ChangeSet::new());
     }
 
-    let catalog_info = ctx
-        .client
-        .get_file_content(owner, &repo.name, "catalog-info.yaml")
-        .await
-        .context(format!(
-            "getting catalog-info.yaml for {}/{}",
-            owner, repo.name
-        ))?;
+    let readme_content = readme
+        .map(|(_, readme)| readme.text.to_owned())
+        .unwrap_or_default();
 
-    debug!("{} has catalog-info.yaml", &repo.name);
+    if args.remove {
+        let modified_readme_content = badges::remove_block(&readme_content, markup);
+        return Ok(readme_change(repo, readme, &readme_content, modified_readme_content));
+    }
 
-    let catalog: Result<backstage::Component> =
-        serde_yaml::from_str(&catalog_info.decoded_content().context(format!(
-            "getting content for catalog-info.yaml for {}/{}",
-            owner, repo.name
-        ))?)
-        .map_err(anyhow::Error::msg)
-        .context(format!(
-            "parsing catalog-info.yaml for {}/{}",
-            owner, repo.name
-        ));
-
-    if matches!(catalog, Result::Err(_)) {
-        warn!("{} does not have a valid catalog-info.yaml", &repo.name);
+    if readme.is_none() && !args.create_readme {
+        warn!("{} has no README, skipping", &repo.name);
         return Ok(ChangeSet::new());
     }
 
-    let component = catalog
-        .context(format!(
-            "parsing catalog-info.yaml for {}/{}",
-            owner, repo.name
-        ))
-        .unwrap();
+    let Some(catalog_info) = blobs.get("catalog-info.yaml") else {
+        warn!("{} has no catalog-info.yaml, skipping", &repo.name);
+        return Ok(ChangeSet::new());
+    };
 
-    // if component.kind != "Component" {
-    //     warn!("{} is not a component, skipping", &repo.name);
-    //     return Ok(ChangeSet::new());
-    // }
+    debug!("{} has catalog-info.yaml", &repo.name);
 
+    // the catalog file can hold API entities and subcomponents as well as the component
+    let component = match backstage::CatalogFile::parse(&catalog_info.text) {
+        Ok(catalog) => catalog.component,
+        Err(err) => {
+            warn!("{} does not have a valid catalog-info.yaml: {err:#}", &repo.name);
+            return Ok(ChangeSet::new());
+        }
+    };
 
+    let base_content = match readme {
+        Some(_) => readme_content.to_owned(),
+        None => {
+            info!("creating a README for {}", &repo.name);
+            readme::minimal(&component, repo)
+        }
+    };
 
-    let owner = &component.spec.owner;
-    let name = &component.metadata.name;
-    let entity_type = component.kind;
-    let modified_readme_content = format!(
-        r#"[![Link to {name} in hipages Developer Portal, {entity_type}: {name}](https://backyard.k8s.hipages.com.au/api/badges/entity/default/{entity_type}/{name}/badge/pingback "Link to {name} in hipages Developer Portal")](https://backyard.k8s.hipages.com.au/catalog/default/{entity_type}/{name})
-[![Entity owner badge, owner: {owner}](https://backyard.k8s.hipages.com.au/api/badges/entity/default/{entity_type}/{name}/badge/owner "Entity owner badge")](https://backyard.k8s.hipages.com.au/catalog/default/{entity_type}/{name})
-{readme_content}"#
+    let modified_readme_content = badges::update_block(
+        &base_content,
+        markup,
+        &badges::badges(&component, repo, &ctx.config.badges),
     );
 
+    Ok(readme_change(repo, readme, &readme_content, modified_readme_content))
+}
+
+fn readme_change(
+    repo: &Repository,
+    readme: Option<(&str, &Blob)>,
+    readme_content: &str,
+    modified_readme_content: String,
+) -> ChangeSet {
     if readme_content != modified_readme_content {
         println!(
             "#{}:\n----\n{}\n\n",
             &repo.name,
-            diff_lines(&readme_content, &modified_readme_content).format_with_context(
+            diff_lines(readme_content, &modified_readme_content).format_with_context(
                 Some(ContextConfig {
                     context_size: 2,
                     skipping_marker: "---"
@@ -165,14 +169,21 @@ async fn add_badge_to_readme(ctx: &Context, repo: &Repository) -> Result<ChangeS
         );
 
         let mut changes = ChangeSet::new();
-        changes.add(Change::UpdateFile {
-            path: readme.path,
-            content: modified_readme_content,
-            sha: readme.sha,
+        changes.add(match readme {
+            Some((path, readme)) => Change::UpdateFile {
+                path: path.to_owned(),
+                content: modified_readme_content,
+                sha: readme.sha.to_owned(),
+                original: readme_content.to_owned(),
+            },
+            None => Change::CreateFile {
+                path: "README.md".to_owned(),
+                content: modified_readme_content,
+            },
         });
         info!("PR created for {}", &repo.name);
-        return Ok(changes);
+        return changes;
     }
 
-    Ok(ChangeSet::new())
+    ChangeSet::new()
 }
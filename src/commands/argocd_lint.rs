@@ -0,0 +1,576 @@
+use crate::{
+    apply_changes,
+    argocd::{self, field, push_missing, AppDestination, AppMetadata, AppSpec, Manifest},
+    argocd_apps::manifest_globs,
+    cli::ArgocdLintArgs,
+    find_repos,
+    pull_request::Message,
+    repo_owner, Change, ChangeSet, Context, Output,
+};
+use anyhow::{Context as anyhowContext, Result};
+use globset::Glob;
+use log::{debug, info, warn};
+use octocrab::models::Repository;
+use prettydiff::{diff_lines, text::ContextConfig};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use std::path::Path;
+
+const RESOURCES_FINALIZER: &str = "resources-finalizer.argocd.argoproj.io";
+
+/// What every Argo CD application in the org has to comply with
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+struct Policy {
+    /// Cluster servers apps may deploy to, any server is allowed when empty
+    allowed_servers: Vec<String>,
+    /// Regexes destination namespaces have to match, any namespace is allowed when empty
+    namespace_patterns: Vec<String>,
+    required_labels: Vec<String>,
+    required_annotations: Vec<String>,
+    forbid_head_revision: bool,
+    /// Revision `HEAD` is replaced with when fixing
+    fix_target_revision: Option<String>,
+    require_finalizer: bool,
+    forbid_default_project: bool,
+}
+
+impl Policy {
+    fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .context(format!("reading policy file {}", path.display()))?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&content).map_err(anyhow::Error::from),
+            _ => serde_yaml::from_str(&content).map_err(anyhow::Error::from),
+        }
+        .context(format!("parsing policy file {}", path.display()))
+    }
+}
+
+#[derive(Serialize)]
+struct Finding {
+    repo: String,
+    path: String,
+    name: String,
+    message: String,
+    /// Whether landscaper can fix the finding
+    fixable: bool,
+    /// Whether the fix was written, which only happens with `--write`
+    fixed: bool,
+}
+
+/// Checks the apps and projects of a manifest file. Findings are kept along with the name of the
+/// app or project and whether they can be fixed.
+struct Linter<'a> {
+    policy: &'a Policy,
+    namespace_patterns: &'a [Regex],
+    /// Name of the app or project being checked
+    name: String,
+    findings: Vec<(String, String, bool)>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, message: String, fixable: bool) {
+        self.findings.push((self.name.to_owned(), message, fixable));
+    }
+
+    /// Lints the documents of a manifest file. With `fix`, fixes are made to the documents in
+    /// place so the rest of the file keeps its comments and formatting, returning the updated file
+    /// when anything was fixed.
+    fn lint_file(&mut self, content: &str, fix: bool) -> Result<Option<String>> {
+        let mut changed = false;
+        let mut updated = String::new();
+
+        for document in argocd::split_documents(content) {
+            let mut value: Value = serde_yaml::from_str(document)?;
+            if value.is_null() {
+                updated.push_str(document);
+                continue;
+            }
+            let before = value.clone();
+
+            let fixable = match Manifest::from_value(value.clone())? {
+                Manifest::Application(app) => {
+                    self.name = app.metadata.name.to_owned();
+                    self.check_app(&app.metadata, &app.spec)
+                }
+                Manifest::ApplicationSet(app_set) => {
+                    self.name = app_set.metadata.name.to_owned();
+                    let template = &app_set.spec.template;
+                    self.check_app(&template.metadata, &template.spec)
+                }
+                Manifest::AppProject(project) => {
+                    self.name = project.metadata.name.to_owned();
+                    for destination in &project.spec.destinations {
+                        self.check_project_destination(destination)?;
+                    }
+                    false
+                }
+                Manifest::Other(_) => false,
+            };
+
+            if fix && fixable {
+                let app = match value["kind"].as_str() {
+                    Some("ApplicationSet") => field(field(&mut value, "spec")?, "template")?,
+                    _ => &mut value,
+                };
+                fix_app(app, self.policy)?;
+            }
+
+            if value == before {
+                updated.push_str(document);
+                continue;
+            }
+
+            changed = true;
+            updated.push_str(&argocd::document_to_yaml(document, &value)?);
+        }
+
+        Ok(changed.then_some(updated))
+    }
+
+    fn check_destination(&mut self, destination: &AppDestination) {
+        let policy = self.policy;
+
+        if let Some(server) = &destination.server {
+            if !policy.allowed_servers.is_empty() && !policy.allowed_servers.contains(server) {
+                self.report(format!("destination server {server} is not allowed"), false);
+            }
+        }
+
+        if let Some(namespace) = &destination.namespace {
+            if !self.namespace_patterns.is_empty()
+                && !self.namespace_patterns.iter().any(|re| re.is_match(namespace))
+            {
+                self.report(format!("destination namespace {namespace} is not allowed"), false);
+            }
+        }
+    }
+
+    /// Checks a destination an AppProject permits, which is a glob such as `*` or `team-*`. A
+    /// server glob has to match one of the allowed servers. Namespace globs can't be compared with
+    /// the namespace patterns, they are left to the apps deploying through the project.
+    fn check_project_destination(&mut self, destination: &AppDestination) -> Result<()> {
+        let policy = self.policy;
+
+        if let Some(server) = &destination.server {
+            let glob = Glob::new(server)
+                .context(format!("parsing destination server {server}"))?
+                .compile_matcher();
+
+            if !policy.allowed_servers.is_empty()
+                && !policy.allowed_servers.iter().any(|allowed| glob.is_match(allowed))
+            {
+                self.report(format!("destination server {server} is not allowed"), false);
+            }
+        }
+
+        match &destination.namespace {
+            Some(namespace) if namespace.contains(['*', '?', '[']) => {
+                debug!("not checking destination namespace {namespace}, it's a glob");
+            }
+            Some(_) => self.check_destination(&AppDestination {
+                server: None,
+                ..destination.clone()
+            }),
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    /// Checks an app or app set template, returning whether any of its findings can be fixed
+    fn check_app(&mut self, metadata: &AppMetadata, spec: &AppSpec) -> bool {
+        let policy = self.policy;
+        let mut fixable = false;
+
+        self.check_destination(&spec.destination);
+
+        for label in &policy.required_labels {
+            if !metadata.labels.contains_key(label) {
+                self.report(format!("missing label {label}"), false);
+            }
+        }
+
+        for annotation in &policy.required_annotations {
+            if !metadata.annotations.contains_key(annotation) {
+                self.report(format!("missing annotation {annotation}"), false);
+            }
+        }
+
+        if policy.forbid_head_revision {
+            for source in spec.source.iter().chain(spec.sources.iter()) {
+                if is_head(source.target_revision.as_deref()) {
+                    fixable |= policy.fix_target_revision.is_some();
+                    self.report(
+                        format!("{} targets HEAD", source.repo_url),
+                        policy.fix_target_revision.is_some(),
+                    );
+                }
+            }
+        }
+
+        if policy.require_finalizer && !metadata.finalizers.iter().any(|f| f == RESOURCES_FINALIZER)
+        {
+            fixable = true;
+            self.report(format!("missing finalizer {RESOURCES_FINALIZER}"), true);
+        }
+
+        if policy.forbid_default_project
+            && spec.project.as_deref().is_none_or(|project| project == "default")
+        {
+            self.report("uses the default project".to_owned(), false);
+        }
+
+        fixable
+    }
+}
+
+/// Whether a source follows the default branch rather than a fixed revision
+fn is_head(target_revision: Option<&str>) -> bool {
+    target_revision.is_none_or(|revision| revision == "HEAD")
+}
+
+/// Fixes the findings of an app, or the template of an app set, that can be fixed
+fn fix_app(app: &mut Value, policy: &Policy) -> Result<()> {
+    let fix_revision = policy.fix_target_revision.as_ref();
+
+    if let Some(revision) = fix_revision.filter(|_| policy.forbid_head_revision) {
+        let fix_source = |source: &mut Value| -> Result<()> {
+            if is_head(source["targetRevision"].as_str()) {
+                *field(source, "targetRevision")? = Value::from(revision.as_str());
+            }
+            Ok(())
+        };
+
+        let spec = field(app, "spec")?;
+        if let Some(source) = spec.get_mut("source").filter(|source| source.is_mapping()) {
+            fix_source(source)?;
+        }
+        if let Some(sources) = spec.get_mut("sources").and_then(Value::as_sequence_mut) {
+            for source in sources {
+                fix_source(source)?;
+            }
+        }
+    }
+
+    if policy.require_finalizer {
+        let finalizers = field(field(app, "metadata")?, "finalizers")?;
+        push_missing(finalizers, &[RESOURCES_FINALIZER.to_owned()])?;
+    }
+
+    Ok(())
+}
+
+/// Lints the repos of every context, printing and reporting the findings of all of them at once
+pub(crate) async fn argocd_lint(contexts: &[Context], args: &ArgocdLintArgs) -> Result<()> {
+    let policy = Policy::load(&args.policy)?;
+    let mut findings = vec![];
+    let mut results = vec![];
+
+    for ctx in contexts {
+        for repo in find_repos(ctx).await? {
+            let changeset = match lint_repo(ctx, &repo, &policy, args, &mut findings).await {
+                Ok(changeset) => changeset,
+                Err(err) => {
+                    warn!("skipping {}, its Argo CD manifests can't be linted: {err:#}", repo.name);
+                    continue;
+                }
+            };
+
+            if changeset.changes.is_empty() {
+                continue;
+            }
+
+            results.push(
+                apply_changes(
+                    ctx,
+                    &repo,
+                    changeset,
+                    Message::new("chore", "Fix Argo CD policy findings"),
+                )
+                .await
+                .context(format!("creating PR for {}", repo.name))?,
+            );
+        }
+    }
+
+    for finding in &findings {
+        println!(
+            "{}/{}{}: {}{}",
+            finding.repo,
+            finding.path,
+            match finding.name.as_str() {
+                "" => String::new(),
+                name => format!(" {name}"),
+            },
+            finding.message,
+            match (finding.fixed, finding.fixable) {
+                (true, _) => " (fixed)",
+                (false, true) => " (fixable)",
+                (false, false) => "",
+            }
+        );
+    }
+    println!("{} findings", findings.len());
+
+    if let Some(report) = &args.report {
+        std::fs::write(report, serde_json::to_string_pretty(&findings)?)
+            .context(format!("writing report {}", report.display()))?;
+    }
+
+    for results in results {
+        if let Output::PullRequest { url } = results {
+            println!("PR: {}", url);
+        }
+    }
+
+    Ok(())
+}
+
+async fn lint_repo(
+    ctx: &Context,
+    repo: &Repository,
+    policy: &Policy,
+    args: &ArgocdLintArgs,
+    findings: &mut Vec<Finding>,
+) -> Result<ChangeSet> {
+    let owner = repo_owner(repo)?;
+    let globs = manifest_globs(ctx)?;
+    let default_branch = repo
+        .default_branch
+        .clone()
+        .context(format!("No default branch for {}", repo.name))?;
+
+    let namespace_patterns = policy
+        .namespace_patterns
+        .iter()
+        .map(|pattern| Regex::new(pattern))
+        .collect::<Result<Vec<_>, _>>()
+        .context("parsing namespace patterns")?;
+
+    let mut changes = ChangeSet::new();
+
+    for path in ctx
+        .client
+        .get_tree_paths(owner, &repo.name, &default_branch)
+        .await?
+        .iter()
+        .filter(|path| globs.is_match(path))
+    {
+        let original = ctx.client.get_file_to_update(owner, &repo.name, path).await?;
+        let content = original
+            .decoded_content()
+            .context(format!("Getting content for {owner}/{}/{path}", repo.name))?;
+
+        let mut linter = Linter {
+            policy,
+            namespace_patterns: &namespace_patterns,
+            name: String::new(),
+            findings: vec![],
+        };
+        let result = linter.lint_file(&content, args.fix);
+
+        for (name, message, fixable) in linter.findings {
+            info!("{}/{} {}: {}", repo.name, path, name, message);
+            findings.push(Finding {
+                repo: repo.name.to_owned(),
+                path: path.to_owned(),
+                name,
+                message,
+                fixable,
+                fixed: fixable && args.fix && ctx.options.write,
+            });
+        }
+
+        // a file that can't be parsed is reported rather than ending the lint of every repo
+        let updated = match result {
+            Ok(Some(updated)) => updated,
+            Ok(None) => continue,
+            Err(err) => {
+                warn!("{}/{} can't be linted: {err:#}", repo.name, path);
+                findings.push(Finding {
+                    repo: repo.name.to_owned(),
+                    path: path.to_owned(),
+                    name: String::new(),
+                    message: format!("can't be parsed: {err:#}"),
+                    fixable: false,
+                    fixed: false,
+                });
+                continue;
+            }
+        };
+
+        println!("{owner}/{}/{path}", repo.name);
+        println!(
+            "{}",
+            diff_lines(&content, &updated).format_with_context(
+                Some(ContextConfig {
+                    context_size: 2,
+                    skipping_marker: "---"
+                }),
+                true
+            )
+        );
+
+        changes.add(Change::UpdateFile {
+            path: path.to_owned(),
+            content: updated,
+            sha: original.sha,
+            original: content,
+        });
+    }
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "
+allowedServers: [https://kubernetes.default.svc]
+namespacePatterns: ['^team-']
+requiredLabels: [team]
+forbidHeadRevision: true
+fixTargetRevision: main
+requireFinalizer: true
+forbidDefaultProject: true
+";
+
+    fn lint(content: &str, fix: bool) -> (Vec<(String, String, bool)>, Option<String>) {
+        let policy: Policy = serde_yaml::from_str(POLICY).unwrap();
+        let namespace_patterns = vec![Regex::new("^team-").unwrap()];
+        let mut linter = Linter {
+            policy: &policy,
+            namespace_patterns: &namespace_patterns,
+            name: String::new(),
+            findings: vec![],
+        };
+
+        let updated = linter.lint_file(content, fix).unwrap();
+        (linter.findings, updated)
+    }
+
+    const APP: &str = "\
+# deployed by the platform team
+apiVersion: argoproj.io/v1alpha1
+kind: Application
+metadata:
+  name: web
+  labels:
+    team: web
+spec:
+  project: web
+  source:
+    repoURL: https://github.com/acme/web
+    targetRevision: HEAD
+  destination:
+    server: https://kubernetes.default.svc
+    namespace: team-web
+";
+
+    #[test]
+    fn reports_findings() {
+        let content = "\
+apiVersion: argoproj.io/v1alpha1
+metadata:
+  name: api
+spec:
+  sources:
+  - repoURL: https://github.com/acme/api
+    targetRevision: v1
+  destination:
+    server: https://other:6443
+    namespace: api
+";
+        let (findings, updated) = lint(content, false);
+        let messages: Vec<_> = findings.iter().map(|(_, message, _)| message.as_str()).collect();
+
+        assert_eq!(
+            messages,
+            [
+                "destination server https://other:6443 is not allowed",
+                "destination namespace api is not allowed",
+                "missing label team",
+                "missing finalizer resources-finalizer.argocd.argoproj.io",
+                "uses the default project",
+            ]
+        );
+        assert!(findings.iter().all(|(name, _, _)| name == "api"));
+        assert_eq!(updated, None);
+    }
+
+    #[test]
+    fn fixes_only_the_affected_documents() {
+        let other = "---\n# untouched\nkind: ConfigMap\nmetadata: {name: config}\n";
+        let (findings, updated) = lint(&format!("{APP}{other}"), true);
+
+        assert_eq!(
+            findings,
+            [
+                ("web".to_owned(), "https://github.com/acme/web targets HEAD".to_owned(), true),
+                (
+                    "web".to_owned(),
+                    "missing finalizer resources-finalizer.argocd.argoproj.io".to_owned(),
+                    true
+                ),
+            ]
+        );
+
+        let updated = updated.unwrap();
+        assert!(updated.ends_with(other));
+        assert!(updated.contains("    targetRevision: main\n"));
+        assert!(updated.contains("  finalizers:\n  - resources-finalizer.argocd.argoproj.io\n"));
+    }
+
+    #[test]
+    fn leaves_file_alone_without_fix() {
+        let (findings, updated) = lint(APP, false);
+
+        assert_eq!(findings.len(), 2);
+        assert_eq!(updated, None);
+    }
+
+    #[test]
+    fn checks_project_destination_globs() {
+        let content = "\
+apiVersion: argoproj.io/v1alpha1
+kind: AppProject
+metadata:
+  name: web
+spec:
+  destinations:
+  - server: '*'
+    namespace: '*'
+  - server: https://other:6443
+    namespace: web
+";
+        let (findings, _) = lint(content, true);
+        let messages: Vec<_> = findings.iter().map(|(_, message, _)| message.as_str()).collect();
+
+        assert_eq!(
+            messages,
+            [
+                "destination server https://other:6443 is not allowed",
+                "destination namespace web is not allowed",
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_on_invalid_documents() {
+        let policy = Policy::default();
+        let mut linter = Linter {
+            policy: &policy,
+            namespace_patterns: &[],
+            name: String::new(),
+            findings: vec![],
+        };
+
+        assert!(linter.lint_file("kind: Application\nspec: [", false).is_err());
+        assert!(serde_yaml::from_str::<Policy>("requireFinalizers: true").is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use crate::{
+    apply_changes,
+    argocd::{self, field, push_missing},
+    argocd_apps::manifest_globs,
+    cli::ArgocdSetArgs,
+    find_repos,
+    pull_request::Message,
+    repo_owner, Change, ChangeSet, Context, Output,
+};
+use anyhow::{Context as anyhowContext, Result};
+use log::{info, warn};
+use octocrab::models::Repository;
+use prettydiff::{diff_lines, text::ContextConfig};
+use serde_yaml::Value;
+
+pub(crate) async fn argocd_set(ctx: &Context, args: &ArgocdSetArgs) -> Result<()> {
+    let mut results = vec![];
+
+    for repo in find_repos(ctx).await? {
+        let changeset = update_manifests(ctx, &repo, args)
+            .await
+            .context(format!("updating Argo CD manifests for {}", repo.name))?;
+
+        if changeset.changes.is_empty() {
+            info!("no changes for {}", repo.name);
+            continue;
+        }
+
+        results.push(
+            apply_changes(
+                ctx,
+                &repo,
+                changeset,
+                match &args.message {
+                    Some(message) => Message::parse(message, "chore"),
+                    None => Message::new("chore", "Update Argo CD applications"),
+                },
+            )
+            .await
+            .context(format!("creating PR for {}", repo.name))?,
+        );
+    }
+
+    for results in results {
+        if let Output::PullRequest { url } = results {
+            println!("PR: {}", url);
+        }
+    }
+
+    Ok(())
+}
+
+async fn update_manifests(
+    ctx: &Context,
+    repo: &Repository,
+    args: &ArgocdSetArgs,
+) -> Result<ChangeSet> {
+    let owner = repo_owner(repo)?;
+    let globs = manifest_globs(ctx)?;
+    let default_branch = repo
+        .default_branch
+        .clone()
+        .context(format!("No default branch for {}", repo.name))?;
+
+    let mut changes = ChangeSet::new();
+
+    for path in ctx
+        .client
+        .get_tree_paths(owner, &repo.name, &default_branch)
+        .await?
+        .iter()
+        .filter(|path| globs.is_match(path))
+    {
+        let original = ctx.client.get_file_to_update(owner, &repo.name, path).await?;
+        let content = original
+            .decoded_content()
+            .context(format!("Getting content for {owner}/{}/{path}", repo.name))?;
+
+        // only the documents of changed apps are rewritten, so the comments and formatting of the
+        // rest of the file are kept
+        let mut changed = false;
+        let mut updated = String::new();
+
+        let documents = argocd::split_documents(&content);
+        let manifests = match documents
+            .iter()
+            .map(|document| serde_yaml::from_str::<Value>(document))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(manifests) => manifests,
+            Err(err) => {
+                warn!("skipping {} for {}/{}, it can't be parsed: {err:#}", path, owner, repo.name);
+                continue;
+            }
+        };
+
+        for (document, mut manifest) in documents.into_iter().zip(manifests) {
+            let edited = match manifest["kind"].as_str() {
+                Some("Application") => edit_app(&mut manifest, args)?,
+                // documents without a kind are apps, as in argocd::parse_manifests
+                None if manifest.is_mapping() => edit_app(&mut manifest, args)?,
+                Some("ApplicationSet") => {
+                    edit_app(field(field(&mut manifest, "spec")?, "template")?, args)?
+                }
+                _ => false,
+            };
+
+            if !edited {
+                updated.push_str(document);
+                continue;
+            }
+
+            changed = true;
+            updated.push_str(&argocd::document_to_yaml(document, &manifest)?);
+        }
+
+        if !changed {
+            continue;
+        }
+
+        println!("{owner}/{}/{path}", repo.name);
+        println!(
+            "{}",
+            diff_lines(&content, &updated).format_with_context(
+                Some(ContextConfig {
+                    context_size: 2,
+                    skipping_marker: "---"
+                }),
+                true
+            )
+        );
+
+        changes.add(Change::UpdateFile {
+            path: path.to_owned(),
+            content: updated,
+            sha: original.sha,
+            original: content,
+        });
+    }
+
+    Ok(changes)
+}
+
+/// Applies the requested changes to an app, or the template of an app set, in place so fields
+/// landscaper doesn't know about keep their order. Returns whether anything changed.
+fn edit_app(app: &mut Value, args: &ArgocdSetArgs) -> Result<bool> {
+    let before = app.clone();
+    let spec = field(app, "spec")?;
+
+    if args.prune.is_some() || args.self_heal.is_some() {
+        let automated = field(field(spec, "syncPolicy")?, "automated")?;
+
+        if let Some(prune) = args.prune {
+            *field(automated, "prune")? = Value::Bool(prune);
+        }
+        if let Some(self_heal) = args.self_heal {
+            *field(automated, "selfHeal")? = Value::Bool(self_heal);
+        }
+    }
+
+    if !args.sync_option.is_empty() {
+        push_missing(field(field(spec, "syncPolicy")?, "syncOptions")?, &args.sync_option)?;
+    }
+
+    if let Some(server) = &args.server {
+        let on_from_server = args
+            .from_server
+            .as_ref()
+            .is_none_or(|from| spec["destination"]["server"].as_str() == Some(from));
+
+        if on_from_server {
+            let destination = field(spec, "destination")?;
+            *field(destination, "server")? = Value::from(server.as_str());
+            // retain keeps the order of the other fields, unlike remove
+            if let Some(destination) = destination.as_mapping_mut() {
+                destination.retain(|key, _| key != "name");
+            }
+        }
+    }
+
+    for (from, to) in &args.rename_namespace {
+        if spec["destination"]["namespace"].as_str() == Some(from) {
+            *field(field(spec, "destination")?, "namespace")? = Value::from(to.as_str());
+        }
+    }
+
+    let metadata = field(app, "metadata")?;
+
+    if !args.finalizer.is_empty() {
+        push_missing(field(metadata, "finalizers")?, &args.finalizer)?;
+    }
+
+    for (key, value) in &args.label {
+        *field(field(metadata, "labels")?, key)? = Value::from(value.as_str());
+    }
+
+    Ok(*app != before)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args() -> ArgocdSetArgs {
+        ArgocdSetArgs {
+            prune: None,
+            self_heal: None,
+            sync_option: vec![],
+            server: None,
+            from_server: None,
+            rename_namespace: vec![],
+            finalizer: vec![],
+            label: vec![],
+            message: None,
+        }
+    }
+
+    fn edit(app: &str, args: &ArgocdSetArgs) -> (bool, String) {
+        let mut app: Value = serde_yaml::from_str(app).unwrap();
+        let changed = edit_app(&mut app, args).unwrap();
+        (changed, serde_yaml::to_string(&app).unwrap())
+    }
+
+    const APP: &str = "\
+metadata:
+  name: web
+  labels:
+    team: web
+spec:
+  destination:
+    name: old
+    namespace: web
+  syncPolicy:
+    syncOptions:
+    - CreateNamespace=true
+";
+
+    #[test]
+    fn adds_missing_fields_in_place() {
+        let args = ArgocdSetArgs {
+            prune: Some(true),
+            sync_option: vec!["CreateNamespace=true".to_owned(), "PruneLast=true".to_owned()],
+            finalizer: vec!["resources-finalizer.argocd.argoproj.io".to_owned()],
+            label: vec![("tier".to_owned(), "frontend".to_owned())],
+            ..args()
+        };
+
+        assert_eq!(
+            edit(APP, &args),
+            (
+                true,
+                "\
+metadata:
+  name: web
+  labels:
+    team: web
+    tier: frontend
+  finalizers:
+  - resources-finalizer.argocd.argoproj.io
+spec:
+  destination:
+    name: old
+    namespace: web
+  syncPolicy:
+    syncOptions:
+    - CreateNamespace=true
+    - PruneLast=true
+    automated:
+      prune: true
+"
+                .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn moves_destination_to_server() {
+        let args = ArgocdSetArgs {
+            server: Some("https://new:6443".to_owned()),
+            rename_namespace: vec![("web".to_owned(), "frontend".to_owned())],
+            ..args()
+        };
+        let (changed, edited) = edit(APP, &args);
+
+        assert!(changed);
+        assert!(
+            edited.contains("  destination:\n    namespace: frontend\n    server: https://new:6443\n")
+        );
+
+        let args = ArgocdSetArgs {
+            from_server: Some("https://other:6443".to_owned()),
+            ..args
+        };
+        let (_, edited) = edit(APP, &args);
+        assert!(edited.contains("  destination:\n    name: old\n    namespace: frontend\n"));
+    }
+
+    #[test]
+    fn reports_unchanged_apps() {
+        let args = ArgocdSetArgs {
+            sync_option: vec!["CreateNamespace=true".to_owned()],
+            label: vec![("team".to_owned(), "web".to_owned())],
+            rename_namespace: vec![("api".to_owned(), "backend".to_owned())],
+            ..args()
+        };
+
+        assert_eq!(edit(APP, &args), (false, APP.to_owned()));
+    }
+}
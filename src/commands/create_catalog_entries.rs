@@ -1,50 +1,35 @@
-use crate::{apply_changes, backstage, Change, ChangeSet, Context, Output};
+use crate::{
+    apply_changes, backstage, find_repos, owners, pull_request::Message, systems::SystemRules,
+    Change, ChangeSet, Context, Output,
+};
 use anyhow::{Context as anyhowContext, Result};
-use log::info;
+use log::warn;
 use octocrab::models::Repository;
-use regex::Regex;
 
 pub(crate) async fn create_missing_catalog_files(ctx: &Context) -> Result<()> {
-    let owner = &ctx.options.org;
-    let repos = ctx
+    let mut results = vec![];
+    let systems = SystemRules::load(&ctx.config)?;
+
+    let repos = find_repos(ctx).await?;
+    let fetched = ctx
         .client
-        .orgs(owner)
-        .list_repos()
-        .sort(octocrab::params::repos::Sort::Pushed)
-        .send()
+        .get_files_batch(&repos, &["catalog-info.yaml", ".argocd.yaml"])
         .await?;
 
-    let mut results = vec![];
-
-    for repo in ctx.client.all_pages(repos).await?.into_iter() {
-        if let Some(filter) = &ctx.options.repo {
-            let re = Regex::new(filter).unwrap();
-            if !re.is_match(&repo.name) {
-                info!(
-                    "Skipping {}/{} as it does not match filter",
-                    owner, repo.name
-                );
+    for (repo, files) in repos.into_iter().zip(fetched) {
+        // a repo that couldn't be fetched may well have a catalog-info.yaml
+        let files = match files {
+            Ok(files) => files,
+            Err(err) => {
+                warn!("skipping {}: {err:#}", repo.name);
                 continue;
             }
-        }
-
-        if let Some(true) = repo.archived {
-            info!("{} is archived", &repo.name);
-            continue;
-        }
-
-        let has_catalog = ctx
-            .client
-            .repos(owner, &repo.name)
-            .get_content()
-            .path("catalog-info.yaml")
-            .send()
-            .await
-            .context(format!("checking {}", repo.name));
+        };
 
-        if has_catalog.is_err() {
+        if files.get("catalog-info.yaml").is_none() {
             println!("{} does not have catalog-info.yaml", &repo.name);
-            let changeset = create_catalog_entry(ctx, &repo)
+            let has_argo = files.get(".argocd.yaml").is_some();
+            let changeset = create_catalog_entry(ctx, &repo, has_argo, &systems)
                 .await
                 .context(format!("creating catalog-info.yaml for {}", repo.name))?;
             results.push(
@@ -52,7 +37,7 @@ pub(crate) async fn create_missing_catalog_files(ctx: &Context) -> Result<()> {
                     ctx,
                     &repo,
                     changeset,
-                    "chore: Add catalog-info.yaml [no-ci]".to_owned(),
+                    Message::new("chore", "Add catalog-info.yaml").skip_ci_suffix("[no-ci]"),
                 )
                 .await
                 .context(format!("creating PR for {}", repo.name))?,
@@ -69,21 +54,26 @@ pub(crate) async fn create_missing_catalog_files(ctx: &Context) -> Result<()> {
     Ok(())
 }
 
-async fn create_catalog_entry(ctx: &Context, repo: &Repository) -> Result<ChangeSet> {
-    let owner = &ctx.options.org;
-
-    let has_argo = ctx
-        .client
-        .repos(owner, &repo.name)
-        .get_content()
-        .path(".argocd.yaml")
-        .send()
+async fn create_catalog_entry(
+    ctx: &Context,
+    repo: &Repository,
+    has_argo: bool,
+    systems: &SystemRules<'_>,
+) -> Result<ChangeSet> {
+    let mut defaults = ctx.config.catalog.defaults_for(repo);
+    defaults.system = systems.system_for(repo, None);
+    if let Some(owner) = owners::infer_owner(ctx, repo)
         .await
-        .context(format!("checking {}", repo.name))
-        .is_ok();
+        .context(format!("finding owner of {}", repo.name))?
+    {
+        defaults.owner = owner;
+    }
 
-    let mut entry =
-        backstage::Component::new(&repo.name, repo.description.clone().unwrap_or_default());
+    let mut entry = backstage::Component::new(
+        &repo.name,
+        repo.description.clone().unwrap_or_default(),
+        defaults,
+    );
 
     entry.metadata.annotations.insert(
         "github.com/project-slug".to_owned(),
@@ -0,0 +1,199 @@
+use crate::{
+    apply_changes, backstage, cli::CreateSystemsArgs, find_repos, pull_request::Message,
+    repo_owner, Change, ChangeSet, Context, Output,
+};
+use anyhow::{Context as anyhowContext, Result};
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Collects the systems referenced by the repos of every context, and opens a single PR on the
+/// catalog repo, which is reached through the first context
+pub(crate) async fn create_missing_system_entities(
+    contexts: &[Context],
+    args: &CreateSystemsArgs,
+) -> Result<()> {
+    // system ref -> owner of the first entity found in it with an owner
+    let mut referenced = BTreeMap::new();
+    let mut defined = BTreeSet::new();
+
+    for ctx in contexts {
+        for repo in find_repos(ctx).await? {
+            let Ok(catalog_info) = ctx
+                .client
+                .get_file_content(repo_owner(&repo)?, &repo.name, "catalog-info.yaml")
+                .await
+            else {
+                debug!("{} does not have catalog-info.yaml", &repo.name);
+                continue;
+            };
+
+            let content = catalog_info.decoded_content().unwrap_or_default();
+            for entity in backstage::parse_entities(&content) {
+                collect_systems(&entity, &mut referenced, &mut defined);
+            }
+        }
+    }
+
+    let ctx = contexts.first().context("no owners to look for systems in")?;
+
+    let (owner, catalog_repo_name) = match args.catalog_repo.split_once('/') {
+        Some((owner, name)) => (owner, name),
+        None => (
+            ctx.options
+                .owners
+                .first()
+                .context("no owner for the catalog repo, pass it as owner/repo")?
+                .as_str(),
+            args.catalog_repo.as_str(),
+        ),
+    };
+
+    let catalog_repo = ctx
+        .client
+        .repos(owner, catalog_repo_name)
+        .get()
+        .await
+        .context(format!("Fetching repo {owner}/{catalog_repo_name}"))?;
+
+    let default_branch = catalog_repo
+        .default_branch
+        .clone()
+        .context(format!("No default branch for {}", args.catalog_repo))?;
+
+    let dir = format!("{}/", args.path.trim_end_matches('/'));
+    for path in ctx
+        .client
+        .get_tree_paths(owner, catalog_repo_name, &default_branch)
+        .await?
+        .iter()
+        .filter(|path| path.starts_with(&dir))
+    {
+        let file = ctx
+            .client
+            .get_file_content(owner, catalog_repo_name, path)
+            .await
+            .context(format!("getting {path} for {owner}/{catalog_repo_name}"))?;
+
+        for entity in backstage::parse_entities(&file.decoded_content().unwrap_or_default()) {
+            collect_systems(&entity, &mut referenced, &mut defined);
+        }
+    }
+
+    let mut changes = ChangeSet::new();
+
+    for (system, system_owner) in referenced
+        .iter()
+        .filter(|(system, _)| !defined.contains(*system))
+    {
+        if system_owner.is_empty() {
+            warn!("{system} is not defined, but none of the entities in it have an owner for it");
+            continue;
+        }
+
+        // refs are expanded to `system:namespace/name` when collected
+        let (namespace, name) = system
+            .trim_start_matches("system:")
+            .split_once('/')
+            .unwrap_or(("default", system));
+        info!("{system} is not defined, creating it");
+
+        // systems with the same name can live in different namespaces
+        let path = match namespace {
+            "default" => format!("{dir}{name}.yaml"),
+            _ => format!("{dir}{namespace}-{name}.yaml"),
+        };
+
+        let entity = backstage::System::new(namespace, name, system_owner);
+        changes.add(Change::CreateFile {
+            path,
+            content: serde_yaml::to_string(&entity)?,
+        });
+    }
+
+    if changes.changes.is_empty() {
+        println!("All referenced systems are defined");
+        return Ok(());
+    }
+
+    let result = apply_changes(
+        ctx,
+        &catalog_repo,
+        changes,
+        Message::new("chore", "Add missing System entities"),
+    )
+    .await
+    .context(format!("creating PR for {}", args.catalog_repo))?;
+
+    if let Output::PullRequest { url } = result {
+        println!("PR: {}", url);
+    }
+
+    Ok(())
+}
+
+fn collect_systems(
+    entity: &backstage::Entity,
+    referenced: &mut BTreeMap<String, String>,
+    defined: &mut BTreeSet<String>,
+) {
+    if entity.kind.eq_ignore_ascii_case("System") {
+        defined.insert(entity.entity_ref());
+        return;
+    }
+
+    if let Some(system) = entity.spec.get("system").and_then(|system| system.as_str()) {
+        let owner = entity
+            .spec
+            .get("owner")
+            .and_then(|owner| owner.as_str())
+            .unwrap_or_default();
+
+        // the first entity with an owner decides, entities without one don't
+        let system_owner: &mut String = referenced
+            .entry(backstage::full_entity_ref(system, "system", entity.namespace()))
+            .or_default();
+        if system_owner.is_empty() {
+            *system_owner = owner.to_owned();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_referenced_and_defined_systems() {
+        let content = "\
+kind: Component
+metadata: {name: cart, namespace: shop}
+spec: {system: checkout}
+---
+kind: Component
+metadata: {name: payments, namespace: shop}
+spec: {system: checkout, owner: group:payments}
+---
+kind: Component
+metadata: {name: web}
+spec: {system: shop/storefront, owner: group:web}
+---
+kind: System
+metadata: {name: billing}
+";
+        let mut referenced = BTreeMap::new();
+        let mut defined = BTreeSet::new();
+
+        for entity in backstage::parse_entities(content) {
+            collect_systems(&entity, &mut referenced, &mut defined);
+        }
+
+        assert_eq!(
+            referenced,
+            BTreeMap::from([
+                ("system:shop/checkout".to_owned(), "group:payments".to_owned()),
+                ("system:shop/storefront".to_owned(), "group:web".to_owned()),
+            ])
+        );
+        assert_eq!(defined, BTreeSet::from(["system:default/billing".to_owned()]));
+    }
+}
@@ -1,43 +1,57 @@
-use crate::{apply_changes, backstage, Change, ChangeSet, Context, Output, argocd};
+use crate::{
+    api_discovery, apply_changes,
+    argocd_apps::RepoApps,
+    backstage,
+    cli::{DriftMode, EnrichArgs},
+    detection::{self, DetectionRule},
+    links, owners,
+    pull_request::Message,
+    readme::{self, Markup},
+    repo_files::{RepoBlobs, RepoFiles},
+    systems::SystemRules,
+    find_repos, repo_owner, tagging, techdocs, Change, ChangeSet, Context, Output,
+};
 use anyhow::{Context as anyhowContext, Result};
-use log::info;
-use octocrab::{models::{Repository, Code}, Page, params::Direction};
+use log::{info, warn};
+use octocrab::{models::{Repository, Code}, Page};
 use prettydiff::{text::ContextConfig, diff_lines};
-use regex::Regex;
 
 
-pub(crate) async fn enrich_catalog_files(ctx: &Context) -> Result<()> {
-    let owner = &ctx.options.org;
-    let repos = ctx
-        .client
-        .orgs(owner)
-        .list_repos()
-        .direction(Direction::Descending)
-        .sort(octocrab::params::repos::Sort::Updated)
-        .send()
-        .await?;
+pub(crate) async fn enrich_catalog_files(ctx: &Context, args: &EnrichArgs) -> Result<()> {
+    let rules = match args.rules.as_ref().or(ctx.config.rules.as_ref()) {
+        Some(path) => detection::load_rules(path)?,
+        None => detection::default_rules()?,
+    };
+    let systems = SystemRules::load(&ctx.config)?;
 
     let mut results = vec![];
 
-    for repo in ctx.client.all_pages(repos).await?.into_iter() {
-        if let Some(true) = repo.archived {
-            info!("{} is archived, skipping", &repo.name);
-            continue;
-        }
+    let repos = find_repos(ctx).await?;
+    // the README is only needed to scaffold TechDocs, and finding it takes another query
+    let fetched: Vec<Result<(Option<String>, RepoBlobs)>> = if args.techdocs_scaffold {
+        readme::fetch_readmes(&ctx.client, &repos, &["catalog-info.yaml"]).await?
+    } else {
+        ctx.client
+            .get_files_batch(&repos, &["catalog-info.yaml"])
+            .await?
+            .into_iter()
+            .map(|blobs| blobs.map(|blobs| (None, blobs)))
+            .collect()
+    };
+
+    for (repo, fetched) in repos.into_iter().zip(fetched) {
+        info!("looking at {}", repo.name);
 
-        if let Some(filter) = &ctx.options.repo {
-            let re = Regex::new(filter).unwrap();
-            if !re.is_match(&repo.name) {
-                info!(
-                    "Skipping {}/{} as it does not match filter",
-                    owner, repo.name
-                );
+        let (readme_path, blobs) = match fetched {
+            Ok(fetched) => fetched,
+            Err(err) => {
+                warn!("skipping {}: {err:#}", repo.name);
                 continue;
             }
-        }
-        info!("looking at {}", repo.name);
+        };
 
-        let changeset = update_catalog_info(ctx, &repo)
+        let readme_path = readme_path.as_deref();
+        let changeset = update_catalog_info(ctx, &repo, &blobs, readme_path, &rules, &systems, args)
             .await
             .context(format!("updating catalog-info.yaml for {}", repo.name))?;
 
@@ -51,7 +65,7 @@ pub(crate) async fn enrich_catalog_files(ctx: &Context) -> Result<()> {
                 ctx,
                 &repo,
                 changeset,
-                "[no-ci] chore: Update catalog.info.yaml".to_owned(),
+                Message::new("chore", "Update catalog-info.yaml").skip_ci_prefix("[no-ci]"),
             )
             .await
             .context(format!("creating PR for {}", repo.name))?,
@@ -67,43 +81,37 @@ pub(crate) async fn enrich_catalog_files(ctx: &Context) -> Result<()> {
     Ok(())
 }
 
-async fn update_catalog_info(ctx: &Context, repo: &Repository) -> Result<ChangeSet> {
-    let owner = &ctx.options.org;
-
-    let catalog_original = ctx
-        .client
-        .get_file_content(owner, &repo.name, "catalog-info.yaml")
-        .await
-        .context(format!(
-            "getting catalog-info.yaml for {}/{}",
-            owner, repo.name
-        ))?;
-
-    let original_content = catalog_original.decoded_content().context(format!(
-        "getting content for catalog-info.yaml for {}/{}",
+async fn update_catalog_info(
+    ctx: &Context,
+    repo: &Repository,
+    blobs: &RepoBlobs,
+    readme_path: Option<&str>,
+    rules: &[DetectionRule],
+    systems: &SystemRules<'_>,
+    args: &EnrichArgs,
+) -> Result<ChangeSet> {
+    let owner = repo_owner(repo)?;
+
+    let catalog_original = blobs.get("catalog-info.yaml").context(format!(
+        "getting catalog-info.yaml for {}/{}",
         owner, repo.name
     ))?;
+    let original_content = catalog_original.text.to_owned();
 
     info!("{} has catalog-info.yaml", &repo.name);
 
-    let catalog: Result<backstage::Component> = serde_yaml::from_str(&original_content)
-        .map_err(anyhow::Error::msg)
-        .context(format!(
-            "parsing catalog-info.yaml for {}/{}",
-            owner, repo.name
-        ));
+    let catalog = backstage::CatalogFile::parse(&original_content).context(format!(
+        "parsing catalog-info.yaml for {}/{}",
+        owner, repo.name
+    ));
 
     if matches!(catalog, Result::Err(_)) {
         info!("{} does not have a valid catalog-info.yaml", &repo.name);
         return Ok(ChangeSet::new());
     }
 
-    let mut component = catalog
-        .context(format!(
-            "parsing catalog-info.yaml for {}/{}",
-            owner, repo.name
-        ))
-        .unwrap();
+    let mut catalog = catalog.unwrap();
+    let component = &mut catalog.component;
 
     info!("{} has a valid catalog-info.yaml", &repo.name);
 
@@ -128,111 +136,159 @@ async fn update_catalog_info(ctx: &Context, repo: &Repository) -> Result<ChangeS
         repo.full_name.to_owned().unwrap_or_default(),
     );
 
-    let argo_file = ctx
-        .client
-        .get_file_content(owner, &repo.name, ".argocd.yaml")
-        .await
-        .context(format!("getting .argocd.yaml for {}/{}", owner, repo.name));
-
-    info!("{} has .argocd.yaml", &repo.name);
-
-    if argo_file.is_ok() {
-        component
-            .metadata
-            .annotations
-            .entry("argocd/app-name".to_owned())
-            .or_insert(repo.name.to_owned());
-
-        let argo_contents = argo_file?.decoded_content().unwrap_or_default();
-        let app_spec: argocd::ArgoApp = serde_yaml::from_str(&argo_contents)?;
-
-        component
-            .metadata
-            .annotations
-            .entry("backstage.io/kubernetes-label-selector".to_string())
-            .or_insert(format!("app.kubernetes.io/instance={}", repo.name));
-
-        component
-            .metadata
-            .annotations
-            .entry("backstage.io/kubernetes-namespace".to_string())
-            .or_insert(app_spec.spec.destination.namespace.to_owned());
+    // only replace owners that were never filled in by a human
+    if component.spec.owner.is_empty() || component.spec.owner == ctx.config.catalog.defaults.owner {
+        if let Some(owner) = owners::infer_owner(ctx, repo)
+            .await
+            .context(format!("finding owner of {}", repo.name))?
+        {
+            component.spec.owner = owner;
+        }
     }
 
-    // legacy DB
-
-    if find_string_in_repo(ctx, repo, "notmidship-db")
-        .await?
-        .total_count
-        .unwrap_or_default()
-        > 0
-    {
-        component
-            .spec
-            .depends_on
-            .insert("resource:hip-rds-mysql-prod".to_owned());
+    let files = if args.local {
+        Some(
+            ctx.client
+                .get_repo_files(owner, &repo.name, &default_branch(repo)?)
+                .await?,
+        )
+    } else {
+        None
+    };
+
+    let paths = match &files {
+        Some(files) => files.iter().map(|(path, _)| path.to_owned()).collect(),
+        None => {
+            ctx.client
+                .get_tree_paths(owner, &repo.name, &default_branch(repo)?)
+                .await?
+        }
+    };
+
+    let apps = RepoApps::find(ctx, repo, &paths, files.as_ref()).await?;
+
+    if let Some(mode) = args.argocd_drift {
+        // missing annotations are added below either way
+        for drift in apps
+            .drift(&catalog.component)
+            .iter()
+            .filter(|drift| drift.current.is_some())
+        {
+            println!(
+                "{}: {} is `{}`, {}",
+                repo.name,
+                drift.annotation,
+                drift.current.as_deref().unwrap_or_default(),
+                match &drift.expected {
+                    Some(expected) => format!("Argo CD has `{expected}`"),
+                    None => "no Argo CD app needs it".to_owned(),
+                }
+            );
+
+            if let DriftMode::Fix = mode {
+                drift.fix(&mut catalog.component);
+            }
+        }
     }
 
-    info!("{} has notmidship-db", &repo.name);
+    apps.annotate(&mut catalog)?;
 
-    // legacy read only DB
-    if find_string_in_repo(ctx, repo, "notmidship-ro-db")
-        .await?
-        .total_count
-        .unwrap_or_default()
-        > 0
-    {
-        component
-            .spec
-            .depends_on
-            .insert("resource:hip-rds-mysql-prod-ro".to_owned());
+    let component = &mut catalog.component;
 
+    if args.systems && component.spec.system.is_none() {
+        component.spec.system = systems.system_for(repo, apps.project());
     }
 
-    info!("{} has notmidship-ro-db", &repo.name);
+    for rule in rules {
+        let matched = match &files {
+            Some(files) => detect_in_files(files, rule),
+            None => detect_with_code_search(ctx, repo, rule).await?,
+        };
 
-    // rabbitmq
-    if find_string_in_repo(ctx, repo, "innocent-chimp")
-        .await?
-        .total_count
-        .unwrap_or_default()
-        > 0
-    {
-        component
-            .spec
-            .depends_on
-            .insert("resource:rabbitmq-innocent-chimp".to_owned());
+        if matched {
+            info!("{} matches rule {}", &repo.name, rule.name);
+            rule.apply(component);
+        }
     }
 
-    info!("{} has rabbitmq", &repo.name);
+    if args.apis {
+        api_discovery::add_provided_apis(&mut catalog, paths.iter().map(String::as_str))?;
+    }
 
-    // kafka
-    if find_string_in_repo(ctx, repo, "kafka-prod")
-        .await?
-        .total_count
-        .unwrap_or_default()
-        > 0
-    {
-        component
-            .spec
-            .depends_on
-            .insert("resource:kafka-prod".to_owned());
+    if args.tags {
+        let config = &ctx.config.tags;
+        let mut tags = tagging::language_tags(ctx, repo, config).await?;
+
+        for manifest in tagging::MANIFESTS
+            .iter()
+            .filter(|manifest| paths.iter().any(|path| path == *manifest))
+        {
+            let content = match &files {
+                Some(files) => files.get(manifest).map(str::to_owned),
+                None => ctx
+                    .client
+                    .get_file_content(owner, &repo.name, manifest)
+                    .await
+                    .context(format!("getting {} for {}/{}", manifest, owner, repo.name))?
+                    .decoded_content(),
+            };
+
+            tags.extend(tagging::manifest_tags(
+                manifest,
+                &content.unwrap_or_default(),
+                config,
+            ));
+        }
+
+        // tags are only ever added, so ones set by hand are kept
+        catalog.component.metadata.tags.extend(tags);
     }
 
-    info!("{} api gateway", &repo.name);
-    // api gateway
-    if find_string_in_repo(ctx, repo, "gloo:")
-        .await?
-        .total_count
-        .unwrap_or_default()
-        > 0
-    {
-        component.spec.depends_on.insert("component:gloo".to_owned());
+    if args.links {
+        // fine-grained tokens need the pages permission to read the site url
+        let pages_url = match repo.has_pages {
+            Some(true) => match ctx.client.get_pages_url(owner, &repo.name).await {
+                Ok(url) => Some(url),
+                Err(err) => {
+                    warn!("not linking the GitHub Pages site of {}: {err:#}", repo.name);
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        links::add_links(&mut catalog.component, repo, pages_url.as_deref(), &ctx.config.links);
     }
 
-    info!("{} has gloo", &repo.name);
+    let mut changes = ChangeSet::new();
 
-    let catalog_updated = serde_yaml::to_string(&component).context(format!(
+    if args.techdocs {
+        let has_techdocs = techdocs::has_techdocs(&paths);
+
+        if !has_techdocs && args.techdocs_scaffold {
+            // other markups can't be used as a mkdocs page
+            let readme = readme_path
+                .filter(|path| Markup::from_path(path) == Markup::Markdown)
+                .and_then(|path| blobs.get(path))
+                .map(|readme| readme.text.to_owned());
+
+            info!("creating TechDocs for {}", &repo.name);
+            for change in techdocs::scaffold(&catalog.component, readme, &paths)? {
+                changes.add(change);
+            }
+        }
+
+        if has_techdocs || args.techdocs_scaffold {
+            catalog
+                .component
+                .metadata
+                .annotations
+                .entry(techdocs::TECHDOCS_REF.to_owned())
+                .or_insert("dir:.".to_owned());
+        }
+    }
+
+    let catalog_updated = catalog.to_yaml().context(format!(
         "serializing catalog-info.yaml for {}/{}",
         owner, repo.name
     ))?;
@@ -252,17 +308,75 @@ async fn update_catalog_info(ctx: &Context, repo: &Repository) -> Result<ChangeS
         );
         info!("waiting or rate limit");
 
-        let mut changes = ChangeSet::new();
         changes.add(Change::UpdateFile {
-            path: catalog_original.path,
+            path: "catalog-info.yaml".to_owned(),
             content: catalog_updated,
-            sha: catalog_original.sha,
+            sha: catalog_original.sha.to_owned(),
+            original: original_content,
         });
+    }
+
+    Ok(changes)
+}
+
+fn default_branch(repo: &Repository) -> Result<String> {
+    repo.default_branch
+        .clone()
+        .context(format!("No default branch for {}", repo.name))
+}
+
+fn detect_in_files(files: &RepoFiles, rule: &DetectionRule) -> bool {
+    files
+        .iter()
+        .any(|(path, content)| rule.matches_path(path) && rule.matches_content(content))
+}
+
+async fn detect_with_code_search(
+    ctx: &Context,
+    repo: &Repository,
+    rule: &DetectionRule,
+) -> Result<bool> {
+    let owner = repo_owner(repo)?;
+
+    let Some(search) = &rule.search else {
+        warn!(
+            "rule {} has no search term, it can't be checked with code search",
+            rule.name
+        );
+        return Ok(false);
+    };
+
+    let first_page = find_string_in_repo(ctx, repo, search).await?;
+
+    // any result will do without a path or regex to check, which saves on the code search quota
+    if rule.path.is_none() && rule.regex.is_none() {
+        return Ok(first_page.total_count.unwrap_or_default() > 0 || !first_page.items.is_empty());
+    }
+
+    // code search returns at most 1000 results, so a term matching more files than that can still
+    // miss the file a path or regex is looking for
+    let mut page = Some(first_page);
+    while let Some(current) = page {
+        for code in current.items.iter().filter(|code| rule.matches_path(&code.path)) {
+            if rule.regex.is_none() {
+                return Ok(true);
+            }
+
+            let file = ctx
+                .client
+                .get_file_content(owner, &repo.name, &code.path)
+                .await
+                .context(format!("getting {} for {}/{}", code.path, owner, repo.name))?;
+
+            if rule.matches_content(&file.decoded_content().unwrap_or_default()) {
+                return Ok(true);
+            }
+        }
 
-        return Ok(changes);
+        page = ctx.client.get_page::<Code>(&current.next).await?;
     }
 
-    Ok(ChangeSet::new())
+    Ok(false)
 }
 
 async fn find_string_in_repo(ctx: &Context, repo: &Repository, needle: &str) -> Result<Page<Code>> {
@@ -270,8 +384,11 @@ async fn find_string_in_repo(ctx: &Context, repo: &Repository, needle: &str) ->
         .search()
         .code(&format!(
             "repo:{}/{} {}",
-            ctx.options.org, repo.name, needle
+            repo_owner(repo)?,
+            repo.name,
+            needle
         ))
+        .per_page(100)
         .send()
         .await
         .map_err(anyhow::Error::from)
@@ -1,4 +1,7 @@
-use crate::{apply_changes, cli::FindReplaceArgs, Change, ChangeSet, Context, Output};
+use crate::{
+    apply_changes, cli::FindReplaceArgs, pull_request::Message, Change, ChangeSet, Context,
+    Output,
+};
 use anyhow::{Context as anyhowContext, Result};
 use itertools::Itertools;
 use octocrab::models::Code;
@@ -34,25 +37,41 @@ async fn find_files(ctx: &Context, args: &FindReplaceArgs) -> Result<HashMap<Str
     Ok(ctx
         .client
         .search()
-        .code(&format!("org:{} {}", ctx.options.org, args.find))
+        .code(&format!("{} {}", owner_qualifiers(ctx), args.find))
         .send()
         .await?
         .into_iter()
-        .into_group_map_by(|f| f.repository.name.to_owned()))
+        .into_group_map_by(|f| {
+            f.repository
+                .full_name
+                .clone()
+                .unwrap_or_else(|| f.repository.name.to_owned())
+        }))
+}
+
+/// Limits a code search to the repos of the owners, `user:` matches orgs as well as users
+fn owner_qualifiers(ctx: &Context) -> String {
+    ctx.options
+        .owners
+        .iter()
+        .map(|owner| format!("user:{owner}"))
+        .join(" ")
 }
 
 async fn find_and_replace_in_repo(
     ctx: &Context,
     args: &FindReplaceArgs,
-    (repo, files): (String, Vec<Code>),
+    (full_name, files): (String, Vec<Code>),
 ) -> Result<Output> {
-    let owner = &ctx.options.org;
+    let (owner, repo) = full_name
+        .split_once('/')
+        .context(format!("{full_name} is not a full repo name"))?;
     let find = &args.find;
     let replace = &args.replace;
 
     if let Some(filter) = &ctx.options.repo {
         let re = Regex::new(filter).unwrap();
-        if !re.is_match(&repo) {
+        if !re.is_match(repo) {
             // println!("Skipping {}/{} as it does not match filter", owner, repo);
             return Ok(Output::Skipped());
         }
@@ -62,7 +81,7 @@ async fn find_and_replace_in_repo(
 
     let repo = ctx
         .client
-        .repos(owner, &repo)
+        .repos(owner, repo)
         .get()
         .await
         .context(format!("Fetching repo {owner}/{repo}"))?;
@@ -73,7 +92,7 @@ async fn find_and_replace_in_repo(
 
     for code in files {
         let path = &code.path;
-        let orginal = ctx.client.get_file_content(owner, repo_name, path).await?;
+        let orginal = ctx.client.get_file_to_update(owner, repo_name, path).await?;
         let content = orginal
             .decoded_content()
             .context(format!("Getting content for {owner}/{repo_name}/{path}"))?;
@@ -101,13 +120,14 @@ async fn find_and_replace_in_repo(
             path: path.to_owned(),
             content: replaced,
             sha: orginal.sha,
+            original: content,
         });
     }
 
-    let message = args
-        .message
-        .clone()
-        .unwrap_or(format!("chore: Replace {} with {}", args.find, args.replace));
+    let message = match &args.message {
+        Some(message) => Message::parse(message, "chore"),
+        None => Message::new("chore", format!("Replace {} with {}", args.find, args.replace)),
+    };
 
     apply_changes(ctx, &repo, changes, message).await
 }
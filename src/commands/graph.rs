@@ -0,0 +1,354 @@
+use crate::{
+    backstage,
+    cli::{GraphArgs, GraphDirection, GraphFormat},
+    find_repos, repo_owner, Context,
+};
+use anyhow::{Context as anyhowContext, Result};
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// Spec fields that reference other entities, and the kind to assume when a ref has none
+const RELATIONS: &[(&str, &str)] = &[
+    ("dependsOn", "component"),
+    ("consumesApis", "api"),
+    ("providesApis", "api"),
+    ("system", "system"),
+    ("subcomponentOf", "component"),
+    ("domain", "domain"),
+];
+
+struct Edge {
+    from: String,
+    to: String,
+    relation: &'static str,
+}
+
+#[derive(Default)]
+struct Graph {
+    /// Every entity defined in a catalog file, and the repo it was found in
+    entities: BTreeMap<String, String>,
+    edges: Vec<Edge>,
+}
+
+impl Graph {
+    fn add_entity(&mut self, repo: &str, entity: &backstage::Entity) {
+        let from = entity.entity_ref();
+
+        for (field, default_kind) in RELATIONS {
+            let targets = match entity.spec.get(*field) {
+                Some(serde_yaml::Value::String(target)) => vec![target.to_owned()],
+                Some(serde_yaml::Value::Sequence(targets)) => targets
+                    .iter()
+                    .filter_map(|target| target.as_str().map(str::to_owned))
+                    .collect(),
+                _ => vec![],
+            };
+
+            for target in targets {
+                self.edges.push(Edge {
+                    from: from.to_owned(),
+                    to: backstage::full_entity_ref(&target, default_kind, entity.namespace()),
+                    relation: field,
+                });
+            }
+        }
+
+        self.entities.insert(from, repo.to_owned());
+    }
+
+    fn nodes(&self) -> BTreeSet<&str> {
+        self.entities
+            .keys()
+            .map(String::as_str)
+            .chain(self.edges.iter().map(|edge| edge.to.as_str()))
+            .collect()
+    }
+
+    fn is_dangling(&self, node: &str) -> bool {
+        !self.entities.contains_key(node)
+    }
+
+    /// Drops every node that can't be reached from `focus` in the given direction
+    fn focus(self, focus: &str, direction: GraphDirection) -> Self {
+        let mut reachable = BTreeSet::from([focus.to_owned()]);
+
+        if direction != GraphDirection::Downstream {
+            reachable.extend(self.walk(focus, |edge| (&edge.from, &edge.to)));
+        }
+        if direction != GraphDirection::Upstream {
+            reachable.extend(self.walk(focus, |edge| (&edge.to, &edge.from)));
+        }
+
+        Self {
+            entities: self
+                .entities
+                .into_iter()
+                .filter(|(entity, _)| reachable.contains(entity))
+                .collect(),
+            edges: self
+                .edges
+                .into_iter()
+                .filter(|edge| reachable.contains(&edge.from) && reachable.contains(&edge.to))
+                .collect(),
+        }
+    }
+
+    fn walk<'a>(
+        &'a self,
+        start: &str,
+        step: impl Fn(&'a Edge) -> (&'a String, &'a String),
+    ) -> BTreeSet<String> {
+        let mut seen = BTreeSet::new();
+        let mut queue = VecDeque::from([start.to_owned()]);
+
+        while let Some(node) = queue.pop_front() {
+            for (from, to) in self.edges.iter().map(&step) {
+                if *from == node && seen.insert(to.to_owned()) {
+                    queue.push_back(to.to_owned());
+                }
+            }
+        }
+
+        seen
+    }
+
+    fn to_dot(&self, dangling: bool) -> String {
+        let mut out = String::from("digraph catalog {\n    rankdir=LR;\n");
+
+        for node in self.nodes() {
+            if dangling && self.is_dangling(node) {
+                out.push_str(&format!("    \"{node}\" [style=dashed, color=red];\n"));
+            } else {
+                out.push_str(&format!("    \"{node}\";\n"));
+            }
+        }
+
+        for edge in &self.edges {
+            out.push_str(&format!(
+                "    \"{}\" -> \"{}\" [label=\"{}\"];\n",
+                edge.from, edge.to, edge.relation
+            ));
+        }
+
+        out.push('}');
+        out
+    }
+
+    fn to_mermaid(&self, dangling: bool) -> String {
+        let ids: BTreeMap<&str, String> = self
+            .nodes()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, node)| (node, format!("n{idx}")))
+            .collect();
+
+        let mut out = String::from("graph LR\n");
+
+        for (node, id) in &ids {
+            out.push_str(&format!("    {id}[\"{node}\"]\n"));
+        }
+
+        for edge in &self.edges {
+            out.push_str(&format!(
+                "    {} -->|{}| {}\n",
+                ids[edge.from.as_str()],
+                edge.relation,
+                ids[edge.to.as_str()]
+            ));
+        }
+
+        if dangling {
+            out.push_str("    classDef dangling stroke:#f00,stroke-dasharray:5 5\n");
+            for (node, id) in &ids {
+                if self.is_dangling(node) {
+                    out.push_str(&format!("    class {id} dangling\n"));
+                }
+            }
+        }
+
+        out
+    }
+
+    fn to_json(&self) -> Result<String> {
+        let nodes: Vec<_> = self
+            .nodes()
+            .into_iter()
+            .map(|node| {
+                serde_json::json!({
+                    "ref": node,
+                    "repo": self.entities.get(node),
+                    "dangling": self.is_dangling(node),
+                })
+            })
+            .collect();
+
+        let edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                serde_json::json!({
+                    "from": edge.from,
+                    "to": edge.to,
+                    "relation": edge.relation,
+                })
+            })
+            .collect();
+
+        serde_json::to_string_pretty(&serde_json::json!({ "nodes": nodes, "edges": edges }))
+            .context("serializing graph")
+    }
+}
+
+/// Builds a single graph from the repos of every context, so entities on different hosts can
+/// reference each other
+pub(crate) async fn dependency_graph(contexts: &[Context], args: &GraphArgs) -> Result<()> {
+    let mut graph = Graph::default();
+
+    for ctx in contexts {
+        add_repos(ctx, &mut graph).await?;
+    }
+
+    info!("{} entities found", graph.entities.len());
+
+    if let Some(focus) = &args.focus {
+        let focus = backstage::full_entity_ref(focus, "component", "default");
+        if graph.is_dangling(&focus) {
+            warn!("{focus} is not defined in any catalog file");
+        }
+        graph = graph.focus(&focus, args.direction);
+    }
+
+    if args.dangling {
+        for edge in graph.edges.iter().filter(|edge| graph.is_dangling(&edge.to)) {
+            eprintln!(
+                "{} ({}) references {} in {} but it does not exist",
+                edge.from, graph.entities[&edge.from], edge.to, edge.relation
+            );
+        }
+    }
+
+    let output = match args.format {
+        GraphFormat::Dot => graph.to_dot(args.dangling),
+        GraphFormat::Mermaid => graph.to_mermaid(args.dangling),
+        GraphFormat::Json => graph.to_json()?,
+    };
+
+    println!("{output}");
+
+    Ok(())
+}
+
+/// Adds the entities in the catalog files of the context's repos to the graph
+async fn add_repos(ctx: &Context, graph: &mut Graph) -> Result<()> {
+    for repo in find_repos(ctx).await? {
+        let owner = repo_owner(&repo)?;
+        let catalog_info = match ctx
+            .client
+            .get_file_content(owner, &repo.name, "catalog-info.yaml")
+            .await
+        {
+            Ok(catalog_info) => catalog_info,
+            Err(_) => {
+                debug!("{} does not have catalog-info.yaml", &repo.name);
+                continue;
+            }
+        };
+
+        let content = catalog_info.decoded_content().context(format!(
+            "getting content for catalog-info.yaml for {}/{}",
+            owner, repo.name
+        ))?;
+
+        for entity in backstage::parse_entities(&content) {
+            graph.add_entity(&repo.name, &entity);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> Graph {
+        let mut graph = Graph::default();
+        let files = [
+            (
+                "cart",
+                "\
+kind: Component
+metadata: {name: cart, namespace: shop}
+spec:
+  system: checkout
+  dependsOn: [resource:default/db, payments]
+  consumesApis: [api:default/prices]
+",
+            ),
+            (
+                "payments",
+                "\
+kind: Component
+metadata: {name: payments, namespace: shop}
+spec: {system: checkout}
+---
+kind: System
+metadata: {name: checkout, namespace: shop}
+",
+            ),
+        ];
+
+        for (repo, content) in files {
+            for entity in backstage::parse_entities(content) {
+                graph.add_entity(repo, &entity);
+            }
+        }
+        graph
+    }
+
+    #[test]
+    fn resolves_refs_in_the_entity_namespace() {
+        let graph = graph();
+        let targets: Vec<_> = graph
+            .edges
+            .iter()
+            .filter(|edge| edge.from == "component:shop/cart")
+            .map(|edge| edge.to.as_str())
+            .collect();
+
+        assert_eq!(
+            targets,
+            [
+                "resource:default/db",
+                "component:shop/payments",
+                "api:default/prices",
+                "system:shop/checkout"
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_dangling_refs() {
+        let graph = graph();
+        let dangling: Vec<_> =
+            graph.nodes().into_iter().filter(|node| graph.is_dangling(node)).collect();
+
+        assert_eq!(dangling, ["api:default/prices", "resource:default/db"]);
+        assert!(graph.to_dot(true).contains("\"resource:default/db\" [style=dashed, color=red]"));
+    }
+
+    #[test]
+    fn focuses_on_connected_entities() {
+        let upstream = graph().focus("component:shop/payments", GraphDirection::Upstream);
+        assert_eq!(
+            upstream.nodes().into_iter().collect::<Vec<_>>(),
+            ["component:shop/payments", "system:shop/checkout"]
+        );
+
+        let downstream = graph().focus("component:shop/payments", GraphDirection::Downstream);
+        assert_eq!(
+            downstream.nodes().into_iter().collect::<Vec<_>>(),
+            ["component:shop/cart", "component:shop/payments"]
+        );
+    }
+}
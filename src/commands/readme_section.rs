@@ -0,0 +1,169 @@
+use crate::{
+    apply_changes, backstage,
+    cli::ReadmeSectionArgs,
+    find_repos,
+    pull_request::Message,
+    readme::{self, Markup, Section},
+    repo_files::RepoBlobs,
+    template::{self, Vars},
+    repo_owner, Change, ChangeSet, Context, Output,
+};
+use anyhow::{Context as anyhowContext, Result};
+use log::{info, warn};
+use octocrab::models::Repository;
+use prettydiff::{diff_lines, text::ContextConfig};
+
+pub(crate) async fn readme_section(ctx: &Context, args: &ReadmeSectionArgs) -> Result<()> {
+    let section = match (&args.heading, &args.marker) {
+        (_, Some(marker)) => Section::marker(marker),
+        (Some(heading), None) => Section::heading(heading, args.level.into()),
+        (None, None) => unreachable!("clap requires a heading or a marker"),
+    };
+
+    let content = match &args.content_file {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .context(format!("reading content file {}", path.display()))?,
+        ),
+        None => args.content.clone(),
+    };
+
+    let mut results = vec![];
+
+    let repos = find_repos(ctx).await?;
+    let fetched = readme::fetch_readmes(&ctx.client, &repos, &["catalog-info.yaml"]).await?;
+
+    for (repo, fetched) in repos.into_iter().zip(fetched) {
+        let (readme_path, blobs) = match fetched {
+            Ok(fetched) => fetched,
+            Err(err) => {
+                warn!("skipping {}: {err:#}", repo.name);
+                continue;
+            }
+        };
+
+        let changeset = update_section(
+            &repo,
+            readme_path.as_deref(),
+            &blobs,
+            &section,
+            content.as_deref(),
+            args,
+        )
+        .context(format!("updating README section for {}", repo.name))?;
+
+        if changeset.changes.is_empty() {
+            info!("no changes for {}", repo.name);
+            continue;
+        }
+
+        let message = match (&args.message, args.remove) {
+            (Some(message), _) => Message::parse(message, "docs"),
+            (None, true) => {
+                Message::new("docs", "Remove section from readme").skip_ci_prefix("[ci-skip]")
+            }
+            (None, false) => {
+                Message::new("docs", "Update section in readme").skip_ci_prefix("[ci-skip]")
+            }
+        };
+
+        results.push(
+            apply_changes(ctx, &repo, changeset, message)
+                .await
+                .context(format!("creating PR for {}", repo.name))?,
+        );
+    }
+
+    for results in results {
+        if let Output::PullRequest { url } = results {
+            println!("PR: {}", url);
+        }
+    }
+
+    Ok(())
+}
+
+fn update_section(
+    repo: &Repository,
+    readme_path: Option<&str>,
+    blobs: &RepoBlobs,
+    section: &Section,
+    content: Option<&str>,
+    args: &ReadmeSectionArgs,
+) -> Result<ChangeSet> {
+    let owner = repo_owner(repo)?;
+
+    let Some(path) = readme_path else {
+        warn!("{} has no README, skipping", &repo.name);
+        return Ok(ChangeSet::new());
+    };
+
+    if Markup::from_path(path) != Markup::Markdown {
+        info!("{} is not Markdown, skipping", path);
+        return Ok(ChangeSet::new());
+    }
+
+    let readme = blobs
+        .get(path)
+        .context(format!("getting {} for {}/{}", path, owner, repo.name))?;
+    let readme_content = &readme.text;
+
+    let updated = match content {
+        Some(content) => {
+            let vars = section_vars(blobs, repo);
+            let is_known = |name: &str| template::ENTITY_VARS.contains(&name);
+            let Some(content) = template::render_known(content, &vars, is_known) else {
+                warn!(
+                    "{} is missing a variable used in the section, skipping",
+                    &repo.name
+                );
+                return Ok(ChangeSet::new());
+            };
+
+            section.upsert(readme_content, &content, args.after.as_deref())
+        }
+        None => section.remove(readme_content),
+    };
+
+    if &updated == readme_content {
+        return Ok(ChangeSet::new());
+    }
+
+    println!("{owner}/{}/{path}", repo.name);
+    println!(
+        "{}",
+        diff_lines(readme_content, &updated).format_with_context(
+            Some(ContextConfig {
+                context_size: 2,
+                skipping_marker: "---"
+            }),
+            true
+        )
+    );
+
+    let mut changes = ChangeSet::new();
+    changes.add(Change::UpdateFile {
+        path: path.to_owned(),
+        content: updated,
+        sha: readme.sha.to_owned(),
+        original: readme_content.to_owned(),
+    });
+
+    Ok(changes)
+}
+
+/// The variables of the repo's catalog entity, or just the repo ones when it doesn't have a valid
+/// catalog-info.yaml
+fn section_vars(blobs: &RepoBlobs, repo: &Repository) -> Vars {
+    let catalog = blobs
+        .get("catalog-info.yaml")
+        .and_then(|file| backstage::CatalogFile::parse(&file.text).ok());
+
+    match catalog {
+        Some(catalog) => template::entity_vars(&catalog.component, repo),
+        None => Vars::from([
+            ("full_name", repo.full_name.clone().unwrap_or_default()),
+            ("default_branch", repo.default_branch.clone().unwrap_or_default()),
+        ]),
+    }
+}
@@ -0,0 +1,649 @@
+use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
+use octocrab::models::Repository;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// Settings read from the landscaper config file
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    pub catalog: CatalogConfig,
+    pub owners: OwnersConfig,
+    pub tags: TagsConfig,
+    pub links: LinksConfig,
+    pub systems: SystemsConfig,
+    pub argocd: ArgocdConfig,
+    pub badges: BadgesConfig,
+    pub github: GithubConfig,
+    pub cache: CacheConfig,
+    /// Detection rules file used by `enrich-catalog-files` when `--rules` isn't passed
+    pub rules: Option<PathBuf>,
+    /// Changes are pushed to `{branch_prefix}/{command}` when `--branch` isn't passed
+    pub branch_prefix: Option<String>,
+    pub pull_requests: PullRequestsConfig,
+    /// Profile used when `--profile` isn't passed
+    pub default_profile: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .context(format!("reading config file {}", path.display()))?;
+
+        let mut config: Self =
+            toml::from_str(&content).context(format!("parsing config file {}", path.display()))?;
+
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+
+        Ok(config)
+    }
+
+    /// Makes the relative paths in the config relative to the dir of the config file, rather than
+    /// the dir landscaper is run in
+    fn resolve_paths(&mut self, dir: &Path) {
+        let resolve = |path: &mut Option<PathBuf>| {
+            if let Some(path) = path.as_mut().filter(|path| path.is_relative()) {
+                *path = dir.join(&*path);
+            }
+        };
+
+        resolve(&mut self.rules);
+        resolve(&mut self.systems.mapping_file);
+        resolve(&mut self.cache.dir);
+        for profile in self.profiles.values_mut() {
+            resolve(&mut profile.rules);
+        }
+    }
+
+    /// Loads the config file at the path, or else the first `landscaper.toml` found in the current
+    /// dir or the user's config dir, eg `~/.config/landscaper/`. The defaults are used without one.
+    pub fn find(path: Option<&Path>) -> Result<Self> {
+        if let Some(path) = path {
+            return Self::load(path);
+        }
+
+        let candidates = config_files(
+            std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from),
+            dirs::home_dir(),
+            dirs::config_dir(),
+        );
+
+        match candidates.into_iter().find(|path| path.is_file()) {
+            Some(path) => {
+                log::info!("using config file {}", path.display());
+                Self::load(&path)
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Applies the settings of a profile over the top level ones. A profile with owners or hosts
+    /// only runs against those, not the top level hosts.
+    pub fn apply_profile(&mut self, profile: &Profile) {
+        if let Some(hosts) = &profile.hosts {
+            self.github.hosts = hosts.clone();
+        } else if !profile.owners.is_empty() {
+            self.github.hosts.clear();
+        }
+        if let Some(url) = &profile.url {
+            self.github.url = Some(url.to_owned());
+        }
+        if let Some(token_env) = &profile.token_env {
+            self.github.token_env = token_env.to_owned();
+        }
+        if let Some(badges) = &profile.badges {
+            self.badges = badges.clone();
+        }
+        if let Some(rules) = &profile.rules {
+            self.rules = Some(rules.to_owned());
+        }
+        if let Some(branch_prefix) = &profile.branch_prefix {
+            self.branch_prefix = Some(branch_prefix.to_owned());
+        }
+        if let Some(pull_requests) = &profile.pull_requests {
+            self.pull_requests = pull_requests.clone();
+        }
+    }
+}
+
+/// Where the config file is looked for after the current dir: the XDG config dir, which is
+/// `~/.config` unless `XDG_CONFIG_HOME` says otherwise, then the platform's config dir, eg
+/// `~/Library/Application Support` on macOS
+fn config_files(
+    xdg_config_home: Option<PathBuf>,
+    home: Option<PathBuf>,
+    config_dir: Option<PathBuf>,
+) -> Vec<PathBuf> {
+    let xdg_config_home = xdg_config_home
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home.map(|home| home.join(".config")));
+
+    let mut files = vec![PathBuf::from("landscaper.toml")];
+    for dir in [xdg_config_home, config_dir].into_iter().flatten() {
+        let file = dir.join("landscaper").join("landscaper.toml");
+        if !files.contains(&file) {
+            files.push(file);
+        }
+    }
+    files
+}
+
+/// Named settings for an org or team, picked with `--profile`. Flags on the command line win over
+/// the profile, which wins over the top level config.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Profile {
+    /// Orgs or users to work on when none are given on the command line
+    #[serde(alias = "orgs")]
+    pub owners: Vec<String>,
+    /// Url of the GitHub Enterprise Server the owners are on
+    pub url: Option<String>,
+    /// Environment variable holding the token for the owners
+    pub token_env: Option<String>,
+    /// The branch changes are pushed to
+    pub branch: Option<String>,
+    /// Changes are pushed to `{branch_prefix}/{command}` when no branch is set
+    pub branch_prefix: Option<String>,
+    /// Regex filter on the repository name
+    pub repo: Option<String>,
+    /// Hosts the profile runs against, instead of the top level ones
+    pub hosts: Option<Vec<GithubHost>>,
+    pub pull_requests: Option<PullRequestsConfig>,
+    pub badges: Option<BadgesConfig>,
+    pub rules: Option<PathBuf>,
+}
+
+/// Templates for the PRs opened by every command. They can use the `repo`, `owner`, `full_name` and
+/// `default_branch` of the repo, the `command` and `campaign`, the Conventional Commit `type`,
+/// `scope`, `summary` and `subject` of the change, the `skip_ci` marker (also split into
+/// `skip_ci_prefix` and `skip_ci_suffix` by where the change puts it), the `changes` list, the
+/// number of `files` and the `additions` and `deletions`. Commit messages can also use the
+/// `action` and `path` of the file.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct PullRequestsConfig {
+    pub title: String,
+    /// Title templates keyed by command, eg `enrich-catalog-files`, used instead of `title`
+    pub titles: BTreeMap<String, String>,
+    pub body: String,
+    pub commit: String,
+    /// Name of the campaign the PRs are part of, available to templates as `{campaign}`
+    pub campaign: Option<String>,
+    /// Marker for changes CI doesn't need to run for, eg ones only touching docs, replacing the
+    /// ones built into the commands
+    pub skip_ci: Option<String>,
+    /// Markers for repos with their own convention, the first rule matching the repo wins
+    pub skip_ci_rules: Vec<SkipCiRule>,
+}
+
+impl Default for PullRequestsConfig {
+    fn default() -> Self {
+        Self {
+            title: "{skip_ci_prefix?} {subject} {skip_ci_suffix?}".to_owned(),
+            titles: BTreeMap::new(),
+            body: "{changes}".to_owned(),
+            commit: "{skip_ci_prefix?} {type}: {action} {path} {skip_ci_suffix?}".to_owned(),
+            campaign: None,
+            skip_ci: None,
+            skip_ci_rules: vec![],
+        }
+    }
+}
+
+impl PullRequestsConfig {
+    /// The skip CI marker used by a repo, empty when it doesn't use one, falling back to the
+    /// command's own marker
+    pub fn skip_ci_marker<'a>(&'a self, repo: &str, default: &'a str) -> Result<&'a str> {
+        for rule in &self.skip_ci_rules {
+            let re = regex::Regex::new(&rule.repo)
+                .context(format!("parsing skip_ci_rules pattern {}", rule.repo))?;
+            if re.is_match(repo) {
+                return Ok(&rule.marker);
+            }
+        }
+
+        Ok(self.skip_ci.as_deref().unwrap_or(default))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SkipCiRule {
+    /// Regex on the repository name
+    pub repo: String,
+    /// Marker used by the matching repos, eg `[ci-skip]`, or empty for none
+    pub marker: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct CatalogConfig {
+    /// Spec values used for generated entries when no rule matches
+    pub defaults: ComponentDefaults,
+    /// Rules applied in order, later matches override earlier ones
+    pub rules: Vec<CatalogRule>,
+}
+
+impl CatalogConfig {
+    /// Works out the spec values for a new entry from the defaults and every rule matching the repo
+    pub fn defaults_for(&self, repo: &Repository) -> ComponentDefaults {
+        let mut defaults = self.defaults.clone();
+
+        for rule in self.rules.iter().filter(|rule| rule.matches(repo)) {
+            if let Some(_type) = &rule._type {
+                defaults._type = _type.to_owned();
+            }
+            if let Some(lifecycle) = &rule.lifecycle {
+                defaults.lifecycle = lifecycle.to_owned();
+            }
+            if let Some(owner) = &rule.owner {
+                defaults.owner = owner.to_owned();
+            }
+            if let Some(system) = &rule.system {
+                defaults.system = Some(system.to_owned());
+            }
+        }
+
+        defaults
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct OwnersConfig {
+    /// Maps GitHub team slugs to backstage owner refs, unmapped teams become `group:<slug>`
+    pub teams: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct TagsConfig {
+    /// Languages making up less than this share of a repo are not tagged
+    pub min_language_share: f64,
+    /// Overrides the tag used for a GitHub language
+    pub languages: BTreeMap<String, String>,
+    /// Maps dependencies found in manifest files to tags
+    pub dependencies: BTreeMap<String, String>,
+    /// Maps docker base images to tags
+    pub images: BTreeMap<String, String>,
+}
+
+impl Default for TagsConfig {
+    fn default() -> Self {
+        let map = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(key, tag)| (key.to_string(), tag.to_string()))
+                .collect()
+        };
+
+        Self {
+            min_language_share: 0.1,
+            languages: BTreeMap::new(),
+            dependencies: map(&[
+                ("laravel/framework", "laravel"),
+                ("symfony/framework-bundle", "symfony"),
+                ("react", "react"),
+                ("next", "nextjs"),
+                ("vue", "vue"),
+                ("express", "express"),
+                ("@nestjs/core", "nestjs"),
+                ("axum", "axum"),
+                ("actix-web", "actix"),
+                ("github.com/gin-gonic/gin", "gin"),
+                ("spring-boot-starter-web", "spring-boot"),
+            ]),
+            images: map(&[
+                ("node", "nodejs"),
+                ("nginx", "nginx"),
+                ("openjdk", "jvm"),
+                ("eclipse-temurin", "jvm"),
+            ]),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LinksConfig {
+    /// Base url of the Argo CD UI, entities with an app name get a link to their application
+    pub argocd_url: Option<String>,
+    /// Extra links, only added when every variable in the url is set for the entity
+    pub templates: Vec<LinkTemplate>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LinkTemplate {
+    pub url: String,
+    pub title: String,
+    #[serde(default)]
+    pub icon: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct SystemsConfig {
+    /// CSV file with `repo` and `system` columns, which takes precedence over any other rule
+    pub mapping_file: Option<PathBuf>,
+    /// Maps Argo CD projects to systems, used when no catalog rule sets a system
+    pub argocd_projects: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ArgocdConfig {
+    /// Globs matching the files in a repo that hold Argo CD manifests
+    pub manifests: Vec<String>,
+}
+
+impl Default for ArgocdConfig {
+    fn default() -> Self {
+        Self {
+            manifests: vec!["**/.argocd.yaml".to_owned()],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct GithubConfig {
+    /// Url of the GitHub Enterprise Server the owners given on the command line are on
+    pub url: Option<String>,
+    /// Environment variable holding the token for those owners
+    pub token_env: String,
+    /// Other hosts and owners every command also runs against
+    pub hosts: Vec<GithubHost>,
+}
+
+impl Default for GithubConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            token_env: "GITHUB_TOKEN".to_owned(),
+            hosts: vec![],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct CacheConfig {
+    /// Where responses are cached, the user's cache dir when not set
+    pub dir: Option<PathBuf>,
+    /// How long a response is used without checking with GitHub whether it changed
+    pub ttl_secs: u64,
+    /// Responses that haven't been fetched or revalidated for this long are deleted
+    pub max_age_days: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            ttl_secs: 0,
+            max_age_days: 30,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct GithubHost {
+    /// Url of the host, github.com when not set
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Environment variable holding the token for this host
+    pub token_env: String,
+    /// Orgs or users on this host
+    #[serde(alias = "orgs")]
+    pub owners: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct BadgesConfig {
+    /// Base url of the Backstage instance, available to badge templates as `{portal_url}`
+    pub portal_url: String,
+    /// Badges added to READMEs in order. Every field can use the `name`, `kind`, `owner`,
+    /// `namespace` and `system` of the entity and the `full_name` and `default_branch` of the repo.
+    pub badges: Vec<BadgeTemplate>,
+}
+
+impl Default for BadgesConfig {
+    fn default() -> Self {
+        let entity = "{portal_url}/catalog/{namespace}/{kind}/{name}";
+        let badge = "{portal_url}/api/badges/entity/{namespace}/{kind}/{name}/badge";
+
+        Self {
+            portal_url: "https://backyard.k8s.hipages.com.au".to_owned(),
+            badges: vec![
+                BadgeTemplate {
+                    alt: "Link to {name} in hipages Developer Portal, {kind}: {name}".to_owned(),
+                    image: format!("{badge}/pingback"),
+                    title: Some("Link to {name} in hipages Developer Portal".to_owned()),
+                    link: Some(entity.to_owned()),
+                },
+                BadgeTemplate {
+                    alt: "Entity owner badge, owner: {owner}".to_owned(),
+                    image: format!("{badge}/owner"),
+                    title: Some("Entity owner badge".to_owned()),
+                    link: Some(entity.to_owned()),
+                },
+            ],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BadgeTemplate {
+    /// Alt text of the image
+    pub alt: String,
+    /// Url of the badge image
+    pub image: String,
+    /// Tooltip of the image
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Where the badge links to
+    #[serde(default)]
+    pub link: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ComponentDefaults {
+    #[serde(rename = "type")]
+    pub _type: String,
+    pub lifecycle: String,
+    pub owner: String,
+    pub system: Option<String>,
+}
+
+impl Default for ComponentDefaults {
+    fn default() -> Self {
+        Self {
+            _type: "service".to_owned(),
+            lifecycle: "experimental".to_owned(),
+            owner: "hipages".to_owned(),
+            system: None,
+        }
+    }
+}
+
+/// Sets spec values on repos matching all of the given conditions
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct CatalogRule {
+    /// Matches repos tagged with this GitHub topic
+    pub topic: Option<String>,
+    /// Matches repos whose name starts with this prefix
+    pub name_prefix: Option<String>,
+    /// Matches repos that haven't been pushed to in this many days
+    pub pushed_days_ago: Option<i64>,
+
+    #[serde(rename = "type")]
+    pub _type: Option<String>,
+    pub lifecycle: Option<String>,
+    pub owner: Option<String>,
+    pub system: Option<String>,
+}
+
+impl CatalogRule {
+    fn matches(&self, repo: &Repository) -> bool {
+        if let Some(topic) = &self.topic {
+            if !repo.topics.iter().flatten().any(|t| t == topic) {
+                return false;
+            }
+        }
+
+        if let Some(prefix) = &self.name_prefix {
+            if !repo.name.starts_with(prefix) {
+                return false;
+            }
+        }
+
+        if let Some(days) = self.pushed_days_ago {
+            let stale = repo
+                .pushed_at
+                .is_none_or(|pushed_at| pushed_at < Utc::now() - Duration::days(days));
+            if !stale {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_readme_examples() {
+        let readme = include_str!("../README.md");
+
+        for block in readme.split("```toml\n").skip(1) {
+            let example = block.split("```").next().unwrap_or_default();
+            if let Err(err) = toml::from_str::<Config>(example) {
+                panic!("{err}\nin\n{example}");
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(toml::from_str::<Config>("[[catalog.rules]]\narchived = true\n").is_err());
+        assert!(toml::from_str::<Config>("[[catalog.rules]]\ntopics = \"web\"\n").is_err());
+        assert!(toml::from_str::<Config>("[profiles.web]\ntoken = \"x\"\n").is_err());
+        assert!(toml::from_str::<Config>("[[catalog.rules]]\ntopic = \"web\"\n").is_ok());
+    }
+
+    const PROFILES: &str = r#"
+branch_prefix = "platform"
+
+[[github.hosts]]
+token_env = "PUBLIC_TOKEN"
+owners = ["acme-oss"]
+
+[profiles.payments]
+owners = ["acme-payments"]
+branch_prefix = "payments"
+rules = "payments-rules.yaml"
+
+[profiles.enterprise]
+url = "https://github.acme.com"
+
+[[profiles.enterprise.hosts]]
+url = "https://github.acme.com"
+token_env = "GHE_TOKEN"
+owners = ["acme"]
+
+[profiles.badges]
+badges = { portal_url = "https://backstage.acme.com" }
+"#;
+
+    #[test]
+    fn applies_profiles() {
+        let config: Config = toml::from_str(PROFILES).unwrap();
+
+        let mut payments: Config = toml::from_str(PROFILES).unwrap();
+        payments.apply_profile(&config.profiles["payments"]);
+        assert_eq!(payments.branch_prefix.as_deref(), Some("payments"));
+        assert_eq!(payments.rules, Some(PathBuf::from("payments-rules.yaml")));
+        assert!(payments.github.hosts.is_empty());
+
+        let mut enterprise: Config = toml::from_str(PROFILES).unwrap();
+        enterprise.apply_profile(&config.profiles["enterprise"]);
+        assert_eq!(enterprise.github.url.as_deref(), Some("https://github.acme.com"));
+        assert_eq!(enterprise.github.hosts.len(), 1);
+        assert_eq!(enterprise.github.hosts[0].owners, ["acme"]);
+        assert_eq!(enterprise.branch_prefix.as_deref(), Some("platform"));
+
+        let mut badges: Config = toml::from_str(PROFILES).unwrap();
+        badges.apply_profile(&config.profiles["badges"]);
+        assert_eq!(badges.badges.portal_url, "https://backstage.acme.com");
+        assert_eq!(badges.github.hosts[0].owners, ["acme-oss"]);
+    }
+
+    #[test]
+    fn resolves_paths_against_config_dir() {
+        let mut config: Config = toml::from_str(
+            r#"
+rules = "rules.yaml"
+systems = { mapping_file = "/etc/landscaper/systems.yaml" }
+cache = { dir = "../cache" }
+
+[profiles.payments]
+rules = "payments/rules.yaml"
+"#,
+        )
+        .unwrap();
+        config.resolve_paths(Path::new("/home/me/landscaper"));
+
+        assert_eq!(config.rules, Some(PathBuf::from("/home/me/landscaper/rules.yaml")));
+        assert_eq!(
+            config.systems.mapping_file,
+            Some(PathBuf::from("/etc/landscaper/systems.yaml"))
+        );
+        assert_eq!(config.cache.dir, Some(PathBuf::from("/home/me/landscaper/../cache")));
+        assert_eq!(
+            config.profiles["payments"].rules,
+            Some(PathBuf::from("/home/me/landscaper/payments/rules.yaml"))
+        );
+    }
+
+    #[test]
+    fn looks_for_config_in_xdg_dir_first() {
+        let home = Some(PathBuf::from("/home/me"));
+        let macos = Some(PathBuf::from("/home/me/Library/Application Support"));
+
+        assert_eq!(
+            config_files(None, home.clone(), macos.clone()),
+            [
+                PathBuf::from("landscaper.toml"),
+                PathBuf::from("/home/me/.config/landscaper/landscaper.toml"),
+                PathBuf::from("/home/me/Library/Application Support/landscaper/landscaper.toml"),
+            ]
+        );
+        assert_eq!(
+            config_files(Some(PathBuf::from("/xdg")), home.clone(), Some(PathBuf::from("/xdg"))),
+            [
+                PathBuf::from("landscaper.toml"),
+                PathBuf::from("/xdg/landscaper/landscaper.toml")
+            ]
+        );
+        assert_eq!(
+            config_files(Some(PathBuf::from("relative")), home, None)[1],
+            PathBuf::from("/home/me/.config/landscaper/landscaper.toml")
+        );
+    }
+}
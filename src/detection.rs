@@ -0,0 +1,213 @@
+use crate::backstage::Component;
+use anyhow::{anyhow, Context, Result};
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use serde::Deserialize;
+use std::path::Path;
+
+/// A rules file, either YAML or TOML depending on the extension
+#[derive(Deserialize)]
+struct RuleFile {
+    rules: Vec<RuleSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RuleSpec {
+    /// Name of the rule, used in log output
+    name: Option<String>,
+    /// Term to search for with code search
+    search: Option<String>,
+    /// Regex the file content has to match
+    regex: Option<String>,
+    /// Glob the file path has to match
+    path: Option<String>,
+    field: RuleField,
+    /// Annotation key, required when the field is `annotations`
+    key: Option<String>,
+    value: String,
+}
+
+/// The part of a catalog entry a rule adds its value to
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum RuleField {
+    DependsOn,
+    ConsumesApis,
+    Tags,
+    Annotations,
+}
+
+/// Detects a dependency or property of a repo from its contents
+pub(crate) struct DetectionRule {
+    pub name: String,
+    pub search: Option<String>,
+    pub regex: Option<Regex>,
+    pub path: Option<GlobMatcher>,
+    pub field: RuleField,
+    pub key: Option<String>,
+    pub value: String,
+}
+
+impl DetectionRule {
+    fn from_spec(spec: RuleSpec) -> Result<Self> {
+        let name = spec
+            .name
+            .or_else(|| spec.search.clone())
+            .or_else(|| spec.regex.clone())
+            .ok_or_else(|| anyhow!("rule for {} needs a search or a regex", spec.value))?;
+
+        if matches!(spec.field, RuleField::Annotations) && spec.key.is_none() {
+            return Err(anyhow!("rule {name} sets an annotation but has no key"));
+        }
+
+        Ok(Self {
+            regex: spec
+                .regex
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .context(format!("parsing regex for rule {name}"))?,
+            path: spec
+                .path
+                .as_deref()
+                .map(|path| Glob::new(path).map(|glob| glob.compile_matcher()))
+                .transpose()
+                .context(format!("parsing path glob for rule {name}"))?,
+            name,
+            search: spec.search,
+            field: spec.field,
+            key: spec.key,
+            value: spec.value,
+        })
+    }
+
+    pub fn matches_path(&self, path: &str) -> bool {
+        self.path.as_ref().is_none_or(|glob| glob.is_match(path))
+    }
+
+    /// Whether the rule matches a file, the search term counts as a literal when there is no regex
+    pub fn matches_content(&self, content: &str) -> bool {
+        match (&self.regex, &self.search) {
+            (Some(regex), _) => regex.is_match(content),
+            (None, Some(search)) => content.contains(search.as_str()),
+            (None, None) => false,
+        }
+    }
+
+    /// Adds the rule's value to the catalog entry
+    pub fn apply(&self, component: &mut Component) {
+        let value = self.value.to_owned();
+
+        match self.field {
+            RuleField::DependsOn => {
+                component.spec.depends_on.insert(value);
+            }
+            RuleField::ConsumesApis => {
+                component.spec.consumes_apis.insert(value);
+            }
+            RuleField::Tags => {
+                component.metadata.tags.insert(value);
+            }
+            RuleField::Annotations => {
+                component
+                    .metadata
+                    .annotations
+                    .insert(self.key.clone().unwrap_or_default(), value);
+            }
+        }
+    }
+}
+
+/// The dependencies landscaper detected before rules could be configured
+const DEFAULT_RULES: &str = include_str!("default_rules.yaml");
+
+pub(crate) fn default_rules() -> Result<Vec<DetectionRule>> {
+    let file: RuleFile = serde_yaml::from_str(DEFAULT_RULES).context("parsing default rules")?;
+
+    file.rules.into_iter().map(DetectionRule::from_spec).collect()
+}
+
+pub(crate) fn load_rules(path: &Path) -> Result<Vec<DetectionRule>> {
+    let content = std::fs::read_to_string(path)
+        .context(format!("reading rules file {}", path.display()))?;
+
+    let file: RuleFile = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&content).map_err(anyhow::Error::from),
+        _ => serde_yaml::from_str(&content).map_err(anyhow::Error::from),
+    }
+    .context(format!("parsing rules file {}", path.display()))?;
+
+    file.rules.into_iter().map(DetectionRule::from_spec).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ComponentDefaults;
+
+    fn rules(yaml: &str) -> Result<Vec<DetectionRule>> {
+        let file: RuleFile = serde_yaml::from_str(yaml)?;
+        file.rules.into_iter().map(DetectionRule::from_spec).collect()
+    }
+
+    #[test]
+    fn parses_default_rules() {
+        let rules = default_rules().unwrap();
+        assert!(!rules.is_empty());
+        assert!(rules.iter().all(|rule| rule.search.is_some()));
+    }
+
+    #[test]
+    fn rejects_incomplete_rules() {
+        assert!(rules("rules: [{field: tags, value: rust}]").is_err());
+        assert!(rules("rules: [{search: sentry, field: annotations, value: x}]").is_err());
+        assert!(rules("rules: [{regex: '(', field: tags, value: x}]").is_err());
+    }
+
+    #[test]
+    fn matches_path_and_content() {
+        let rules = rules(
+            "
+rules:
+  - search: redis
+    path: '**/package.json'
+    field: dependsOn
+    value: resource:redis
+  - search: kafka
+    regex: 'kafka-clients:\\d'
+    field: tags
+    value: kafka
+",
+        )
+        .unwrap();
+
+        assert_eq!(rules[0].name, "redis");
+        assert!(rules[0].matches_path("web/package.json"));
+        assert!(!rules[0].matches_path("web/pom.xml"));
+        assert!(rules[0].matches_content("\"redis\": \"^4\""));
+        assert!(rules[1].matches_path("pom.xml"));
+        assert!(rules[1].matches_content("org.apache.kafka:kafka-clients:3.6"));
+        assert!(!rules[1].matches_content("kafka"));
+    }
+
+    #[test]
+    fn applies_value_to_field() {
+        let rules = rules(
+            "
+rules:
+  - {search: redis, field: dependsOn, value: resource:redis}
+  - {search: sentry, field: annotations, key: sentry.io/project, value: web}
+",
+        )
+        .unwrap();
+        let mut component = Component::new("web", "", ComponentDefaults::default());
+
+        for rule in &rules {
+            rule.apply(&mut component);
+        }
+
+        assert!(component.spec.depends_on.contains("resource:redis"));
+        assert_eq!(component.metadata.annotations["sentry.io/project"], "web");
+    }
+}
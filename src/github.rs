@@ -1,14 +1,34 @@
 use anyhow::{anyhow, Context, Result};
-use http::Uri;
+use http::{StatusCode, Uri};
 use octocrab::{
     map_github_error,
-    models::repos::{Content, Object, Ref},
+    models::{
+        repos::{Content, Object, Ref},
+        Repository,
+    },
     params::repos::Reference,
     Octocrab,
 };
+use std::sync::Arc;
 
+use crate::{
+    cache::{Entry, HttpCache},
+    repo_files::{Blob, RepoBlobs, RepoFiles},
+    repo_owner,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+
+/// Repos fetched per GraphQL query, which keeps responses well under GitHub's limits
+const BATCH_SIZE: usize = 25;
+
+#[derive(Clone)]
 pub(crate) struct GithubClient {
     client: Octocrab,
+    /// Client for the GraphQL API, which GitHub Enterprise Server serves outside `/api/v3`
+    graphql_client: Octocrab,
+    /// Url of the REST API, prefixed to routes to key the cache
+    base_url: String,
+    cache: Option<Arc<HttpCache>>,
 }
 
 impl std::ops::Deref for GithubClient {
@@ -20,9 +40,94 @@ impl std::ops::Deref for GithubClient {
 }
 
 impl GithubClient {
-    pub fn new(token: String) -> Self {
-        let client = Octocrab::builder().personal_token(token).build().unwrap();
-        Self { client }
+    /// Creates a client for github.com, or for the GitHub Enterprise Server at the url. Only the
+    /// API url is set: octocrab 0.29 has no setting for the uploads url (`/api/uploads` on GitHub
+    /// Enterprise Server), which only release asset uploads use and landscaper never makes.
+    pub fn new(token: String, url: Option<&str>, cache: Option<Arc<HttpCache>>) -> Result<Self> {
+        let mut builder = Octocrab::builder().personal_token(token.clone());
+        let api_url = url.and_then(api_url);
+
+        if let Some(api_url) = &api_url {
+            builder = builder
+                .base_uri(api_url.as_str())
+                .context(format!("parsing GitHub url {api_url}"))?;
+        }
+
+        let client = builder.build()?;
+        let graphql_client = match &api_url {
+            Some(api_url) => Octocrab::builder()
+                .personal_token(token)
+                .base_uri(api_url.trim_end_matches("/v3"))
+                .context(format!("parsing GitHub url {api_url}"))?
+                .build()?,
+            None => client.clone(),
+        };
+
+        Ok(Self {
+            client,
+            graphql_client,
+            base_url: api_url.unwrap_or_else(|| "https://api.github.com".to_owned()),
+            cache,
+        })
+    }
+
+    /// Gets a route, going through the cache when there is one. Cached responses are used as they
+    /// are within the ttl, and revalidated with a conditional request after it.
+    pub async fn get_cached<R: DeserializeOwned>(&self, route: &str) -> Result<R> {
+        self.get_through_cache(route, false).await
+    }
+
+    /// Gets a route like [`Self::get_cached`], but always revalidates the cached response. Used
+    /// for what a change is computed from, as a stale sha makes the update fail.
+    pub async fn get_fresh<R: DeserializeOwned>(&self, route: &str) -> Result<R> {
+        self.get_through_cache(route, true).await
+    }
+
+    async fn get_through_cache<R: DeserializeOwned>(
+        &self,
+        route: &str,
+        revalidate: bool,
+    ) -> Result<R> {
+        let Some(cache) = &self.cache else {
+            return Ok(self.get(route, None::<&()>).await?);
+        };
+
+        let url = format!("{}{route}", self.base_url);
+        let cached = cache.get(&url);
+
+        if let Some(entry) = cached
+            .as_ref()
+            .filter(|entry| !revalidate && cache.is_fresh(entry))
+        {
+            return serde_json::from_str(&entry.body).context(format!("parsing cached {url}"));
+        }
+
+        let response = self
+            ._get_with_headers(route, cached.as_ref().map(Entry::conditional_headers))
+            .await?;
+
+        let entry = match cached {
+            Some(mut entry) if response.status() == StatusCode::NOT_MODIFIED => {
+                log::debug!("{url} not modified");
+                entry.touch();
+                entry
+            }
+            _ => {
+                let response = map_github_error(response).await?;
+                let headers = response.headers().clone();
+                let body = hyper::body::to_bytes(response.into_body())
+                    .await
+                    .context(format!("reading {url}"))?;
+
+                Entry::new(&url, &headers, String::from_utf8_lossy(&body).into_owned())
+            }
+        };
+
+        if let Err(err) = cache.put(&entry) {
+            log::warn!("could not cache {url}: {err:#}");
+        }
+
+        serde_json::from_str(&entry.body).context(format!("parsing {url}"))
     }
 
     pub async fn get_sha_for_ref(
@@ -56,16 +161,261 @@ impl GithubClient {
     }
 
     pub async fn get_file_content(&self, owner: &str, repo: &str, path: &str) -> Result<Content> {
-        self.repos(owner, repo)
-            .get_content()
-            .path(path)
-            .send()
-            .await?
-            .items
-            .pop()
+        self.get_cached(&format!("/repos/{owner}/{repo}/contents/{path}"))
+            .await
             .context("Getting file content")
     }
 
+    /// Gets a file that is about to be updated, checking with GitHub that it's the latest version
+    pub async fn get_file_to_update(&self, owner: &str, repo: &str, path: &str) -> Result<Content> {
+        self.get_fresh(&format!("/repos/{owner}/{repo}/contents/{path}"))
+            .await
+            .context("Getting file content")
+    }
+
+    /// The url of the repo's GitHub Pages site, which may be a custom domain
+    pub async fn get_pages_url(&self, owner: &str, repo: &str) -> Result<String> {
+        #[derive(Deserialize)]
+        struct Pages {
+            html_url: String,
+        }
+
+        let pages: Pages = self
+            .get_cached(&format!("/repos/{owner}/{repo}/pages"))
+            .await
+            .context(format!("getting GitHub Pages site of {owner}/{repo}"))?;
+
+        Ok(pages.html_url)
+    }
+
+    /// Fetches the files and directory listings at the paths on the default branch of each repo,
+    /// with one GraphQL query per batch of repos. `""` lists the root, and paths a repo doesn't
+    /// have are left out. The results are in the order of `requests`, with an error for each repo
+    /// that couldn't be fetched, which callers skip rather than mistake for one without the files.
+    pub async fn get_blobs_batch(
+        &self,
+        requests: &[(&Repository, Vec<String>)],
+    ) -> Result<Vec<Result<RepoBlobs>>> {
+        let mut results = Vec::with_capacity(requests.len());
+
+        for batch in requests.chunks(BATCH_SIZE) {
+            results.extend(self.get_blobs(batch).await?);
+        }
+
+        Ok(results)
+    }
+
+    /// Fetches the same paths from every repo, see `get_blobs_batch`
+    pub async fn get_files_batch(
+        &self,
+        repos: &[Repository],
+        paths: &[&str],
+    ) -> Result<Vec<Result<RepoBlobs>>> {
+        let paths: Vec<String> = paths.iter().map(|path| path.to_string()).collect();
+        let requests: Vec<_> = repos.iter().map(|repo| (repo, paths.clone())).collect();
+
+        self.get_blobs_batch(&requests).await
+    }
+
+    async fn get_blobs(
+        &self,
+        requests: &[(&Repository, Vec<String>)],
+    ) -> Result<Vec<Result<RepoBlobs>>> {
+        if requests.iter().all(|(_, paths)| paths.is_empty()) {
+            return Ok(requests.iter().map(|_| Ok(RepoBlobs::default())).collect());
+        }
+
+        let mut query = String::from("query {");
+
+        for (idx, (repo, paths)) in requests.iter().enumerate() {
+            if paths.is_empty() {
+                continue;
+            }
+
+            query += &format!(
+                " r{idx}: repository(owner: {}, name: {}) {{",
+                serde_json::to_string(repo_owner(repo)?)?,
+                serde_json::to_string(&repo.name)?
+            );
+            for (path_idx, path) in paths.iter().enumerate() {
+                query += &format!(
+                    " f{path_idx}: object(expression: {}) {{ \
+                     ... on Blob {{ oid text isTruncated }} \
+                     ... on Tree {{ entries {{ name type }} }} }}",
+                    serde_json::to_string(&format!("HEAD:{path}"))?
+                );
+            }
+            query += " }";
+        }
+        query += " }";
+
+        let response: serde_json::Value = self
+            .graphql_client
+            .graphql(&serde_json::json!({ "query": query }))
+            .await
+            .context("fetching files with GraphQL")?;
+
+        if response["data"].is_null() {
+            return Err(anyhow!("fetching files with GraphQL: {}", response["errors"]));
+        }
+
+        let errors = response["errors"].as_array().cloned().unwrap_or_default();
+
+        // errors of repos that couldn't be fetched are returned with them below
+        for error in &errors {
+            let alias = error["path"][0].as_str().unwrap_or_default();
+            if alias.is_empty() || !response["data"][alias].is_null() {
+                log::warn!("{}", error["message"]);
+            }
+        }
+
+        let mut results = vec![];
+
+        for (idx, (repo, paths)) in requests.iter().enumerate() {
+            let alias = format!("r{idx}");
+            let fetched = &response["data"][&alias];
+
+            if fetched.is_null() && !paths.is_empty() {
+                results.push(Err(anyhow!(
+                    "fetching files of {} with GraphQL: {}",
+                    repo.name,
+                    error_messages(&errors, &alias).join(", ")
+                )));
+                continue;
+            }
+
+            results.push(self.repo_blobs(repo, paths, fetched).await);
+        }
+
+        Ok(results)
+    }
+
+    /// Reads the files and directory listings of a repo from its part of a GraphQL response
+    async fn repo_blobs(
+        &self,
+        repo: &Repository,
+        paths: &[String],
+        fetched: &serde_json::Value,
+    ) -> Result<RepoBlobs> {
+        let (mut blobs, truncated) = read_blobs(paths, fetched);
+
+        // GraphQL only returns the start of large files, and the contents API leaves the content
+        // out of files over 1MB
+        for path in truncated {
+            let owner = repo_owner(repo)?;
+            let content = self.get_file_content(owner, &repo.name, path).await?;
+            let text = content
+                .decoded_content()
+                .context(format!("{path} of {owner}/{} is too large", repo.name))?;
+
+            blobs.blobs.insert(
+                path.to_owned(),
+                Blob {
+                    text,
+                    sha: content.sha,
+                },
+            );
+        }
+
+        Ok(blobs)
+    }
+
+    /// Lists the repos of an org or user, most recently updated first
+    pub async fn list_owner_repos(&self, owner: &str) -> Result<Vec<Repository>> {
+        #[derive(Deserialize)]
+        struct Account {
+            #[serde(rename = "type")]
+            _type: String,
+        }
+
+        let account: Account = self
+            .get_cached(&format!("/users/{owner}"))
+            .await
+            .context(format!("getting account {owner}"))?;
+
+        // the public users endpoint leaves out private repos, which only the user can list
+        let is_current_user = account._type != "Organization"
+            && self
+                .current()
+                .user()
+                .await
+                .context("getting the authenticated user")?
+                .login
+                .eq_ignore_ascii_case(owner);
+
+        // Pages are fetched by number rather than by following links, so each can be cached
+        let route = if account._type == "Organization" {
+            format!("/orgs/{owner}/repos?sort=updated&direction=desc&per_page=100")
+        } else if is_current_user {
+            "/user/repos?affiliation=owner&sort=updated&direction=desc&per_page=100".to_owned()
+        } else {
+            format!("/users/{owner}/repos?type=owner&sort=updated&direction=desc&per_page=100")
+        };
+
+        let mut repos = vec![];
+        for page in 1.. {
+            let page: Vec<Repository> = self
+                .get_cached(&format!("{route}&page={page}"))
+                .await
+                .context(format!("listing repos of {owner}"))?;
+            let last = page.len() < 100;
+
+            repos.extend(page);
+            if last {
+                break;
+            }
+        }
+
+        Ok(repos)
+    }
+
+    /// Lists the path of every file in a repo at a ref
+    pub async fn get_tree_paths(&self, owner: &str, repo: &str, reference: &str) -> Result<Vec<String>> {
+        #[derive(Deserialize)]
+        struct Tree {
+            tree: Vec<TreeEntry>,
+            truncated: bool,
+        }
+
+        #[derive(Deserialize)]
+        struct TreeEntry {
+            path: String,
+            #[serde(rename = "type")]
+            _type: String,
+        }
+
+        let tree: Tree = self
+            .get_cached(&format!("/repos/{owner}/{repo}/git/trees/{reference}?recursive=1"))
+            .await
+            .context(format!("getting tree for {owner}/{repo}"))?;
+
+        if tree.truncated {
+            log::warn!("tree for {owner}/{repo} is too large and was truncated");
+        }
+
+        Ok(tree
+            .tree
+            .into_iter()
+            .filter(|entry| entry._type == "blob")
+            .map(|entry| entry.path)
+            .collect())
+    }
+
+    /// Downloads the text files of a repo at a ref in a single request
+    pub async fn get_repo_files(&self, owner: &str, repo: &str, reference: &str) -> Result<RepoFiles> {
+        let response = self
+            .repos(owner, repo)
+            .download_tarball(reference.to_owned())
+            .await
+            .context(format!("downloading tarball of {owner}/{repo}"))?;
+
+        let tarball = hyper::body::to_bytes(map_github_error(response).await?.into_body())
+            .await
+            .context(format!("reading tarball of {owner}/{repo}"))?;
+
+        RepoFiles::from_tarball(&tarball).context(format!("extracting tarball of {owner}/{repo}"))
+    }
+
     pub async fn delete_ref_if_exists(
         &self,
         owner: &str,
@@ -90,3 +440,109 @@ impl GithubClient {
             .context(format!("Error deleting ref {route}"))
     }
 }
+
+/// The messages of the GraphQL errors about the repo queried under the alias
+fn error_messages<'a>(errors: &'a [serde_json::Value], alias: &str) -> Vec<&'a str> {
+    errors
+        .iter()
+        .filter(|error| error["path"][0] == alias)
+        .filter_map(|error| error["message"].as_str())
+        .collect()
+}
+
+/// Reads the files and directory listings of a repo from its part of a GraphQL response. Also
+/// returns the paths of files whose text was truncated, which have to be fetched separately.
+fn read_blobs<'a>(paths: &'a [String], fetched: &serde_json::Value) -> (RepoBlobs, Vec<&'a str>) {
+    let mut blobs = RepoBlobs::default();
+    let mut truncated = vec![];
+
+    for (path_idx, path) in paths.iter().enumerate() {
+        let object = &fetched[&format!("f{path_idx}")];
+
+        if let Some(entries) = object["entries"].as_array() {
+            blobs.listed.extend(
+                entries
+                    .iter()
+                    .filter(|entry| entry["type"] == "blob")
+                    .filter_map(|entry| entry["name"].as_str())
+                    .map(|name| match path.as_str() {
+                        "" => name.to_owned(),
+                        dir => format!("{dir}/{name}"),
+                    }),
+            );
+        } else if object["isTruncated"] == true {
+            truncated.push(path.as_str());
+        } else if let (Some(sha), Some(text)) = (object["oid"].as_str(), object["text"].as_str()) {
+            blobs.blobs.insert(
+                path.to_owned(),
+                Blob {
+                    sha: sha.to_owned(),
+                    text: text.to_owned(),
+                },
+            );
+        }
+    }
+
+    (blobs, truncated)
+}
+
+/// The REST API url of a GitHub host, None for github.com which octocrab uses by default
+fn api_url(url: &str) -> Option<String> {
+    let url = url.trim_end_matches('/');
+
+    if matches!(
+        url.trim_start_matches("https://").trim_start_matches("http://"),
+        "github.com" | "api.github.com" | "www.github.com"
+    ) {
+        return None;
+    }
+
+    if url.ends_with("/api/v3") {
+        Some(url.to_owned())
+    } else {
+        Some(format!("{url}/api/v3"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_blobs_and_listings() {
+        let paths: Vec<String> = ["", "docs", "catalog-info.yaml", "big.yaml", "missing.yaml"]
+            .map(String::from)
+            .to_vec();
+        let fetched = json!({
+            "f0": { "entries": [
+                { "name": "README.md", "type": "blob" },
+                { "name": "docs", "type": "tree" },
+            ] },
+            "f1": { "entries": [{ "name": "index.md", "type": "blob" }] },
+            "f2": { "oid": "abc", "text": "kind: Component\n", "isTruncated": false },
+            "f3": { "oid": "def", "text": "kind: ", "isTruncated": true },
+            "f4": null,
+        });
+
+        let (blobs, truncated) = read_blobs(&paths, &fetched);
+
+        assert_eq!(blobs.listed, ["README.md", "docs/index.md"]);
+        assert_eq!(blobs.blobs.keys().collect::<Vec<_>>(), ["catalog-info.yaml"]);
+        let blob = blobs.get("catalog-info.yaml").unwrap();
+        assert_eq!((blob.sha.as_str(), blob.text.as_str()), ("abc", "kind: Component\n"));
+        assert_eq!(truncated, ["big.yaml"]);
+    }
+
+    #[test]
+    fn picks_the_errors_of_a_repo() {
+        let errors = [
+            json!({ "path": ["r0"], "message": "Could not resolve to a Repository" }),
+            json!({ "path": ["r1", "f0"], "message": "Something else" }),
+            json!({ "message": "Rate limited" }),
+        ];
+
+        assert_eq!(error_messages(&errors, "r0"), ["Could not resolve to a Repository"]);
+        assert_eq!(error_messages(&errors, "r2"), Vec::<&str>::new());
+    }
+}
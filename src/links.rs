@@ -0,0 +1,73 @@
+use crate::{
+    backstage::{Component, Link},
+    config::LinksConfig,
+    template::{self, Vars},
+};
+use octocrab::models::Repository;
+
+/// Adds links to the repo's homepage, GitHub Pages site, Argo CD application and any configured
+/// link templates, skipping urls the entity already links to
+pub(crate) fn add_links(
+    component: &mut Component,
+    repo: &Repository,
+    pages_url: Option<&str>,
+    config: &LinksConfig,
+) {
+    let vars = link_vars(component, repo);
+    let mut links = vec![];
+
+    if let Some(homepage) = repo.homepage.as_ref().filter(|homepage| !homepage.is_empty()) {
+        links.push(Link::new(homepage, "Homepage", "web"));
+    }
+
+    if let Some(url) = pages_url {
+        links.push(Link::new(url, "GitHub Pages", "docs"));
+    }
+
+    if let Some(argocd_url) = &config.argocd_url {
+        let url = format!("{}/applications/{{argocd_app}}", argocd_url.trim_end_matches('/'));
+        if let Some(url) = template::render(&url, &vars) {
+            links.push(Link::new(url, "Argo CD", "dashboard"));
+        }
+    }
+
+    for link in &config.templates {
+        if let Some(url) = template::render(&link.url, &vars) {
+            links.push(Link::new(url, &link.title, &link.icon));
+        }
+    }
+
+    for link in links {
+        if !component.metadata.links.iter().any(|existing| existing.url == link.url) {
+            component.metadata.links.push(link);
+        }
+    }
+}
+
+fn link_vars(component: &Component, repo: &Repository) -> Vars {
+    let annotation = |key: &str| {
+        component
+            .metadata
+            .annotations
+            .get(key)
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    Vars::from([
+        ("name", component.metadata.name.to_owned()),
+        ("kind", component.kind.to_lowercase()),
+        ("namespace", annotation("backstage.io/kubernetes-namespace")),
+        ("system", component.spec.system.clone().unwrap_or_default()),
+        ("argocd_app", annotation("argocd/app-name")),
+        ("repo", repo.name.to_owned()),
+        ("full_name", repo.full_name.clone().unwrap_or_default()),
+        (
+            "owner",
+            repo.owner
+                .as_ref()
+                .map(|owner| owner.login.to_lowercase())
+                .unwrap_or_default(),
+        ),
+    ])
+}
@@ -1,18 +1,40 @@
 extern crate log;
 
-use anyhow::{Context as anyhowContext, Result};
-use clap::Parser;
+use anyhow::{bail, Context as anyhowContext, Result};
+use clap::{CommandFactory, FromArgMatches};
 use cli::{Command, GlobalOpts};
+use log::info;
 use octocrab::{models::Repository, params::repos::Reference};
-use std::env;
+use regex::Regex;
+use std::{env, sync::Arc};
 
+mod api_discovery;
 mod argocd;
+mod argocd_apps;
 mod backstage;
+mod badges;
+mod cache;
 mod cli;
 mod commands;
+mod config;
+mod detection;
 mod github;
+mod links;
+mod owners;
+mod pull_request;
+mod readme;
+mod repo_files;
+mod systems;
+mod tagging;
+mod techdocs;
+mod template;
 
-use crate::github::GithubClient;
+use crate::{
+    cache::HttpCache,
+    config::Config,
+    github::GithubClient,
+    pull_request::{Message, PullRequest},
+};
 
 #[derive(Debug)]
 struct ChangeSet {
@@ -46,17 +68,27 @@ enum Change {
         path: String,
         content: String,
         sha: String,
+        /// Content before the change, for the diff stats in the PR
+        original: String,
     },
 }
 
 struct Context {
     client: GithubClient,
     options: GlobalOpts,
+    config: Arc<Config>,
+    /// Name of the command being run, eg `enrich-catalog-files`
+    command: String,
 }
 
 impl Context {
-    fn new(client: GithubClient, options: GlobalOpts) -> Self {
-        Self { client, options }
+    fn new(client: GithubClient, options: GlobalOpts, config: Arc<Config>, command: &str) -> Self {
+        Self {
+            client,
+            options,
+            config,
+            command: command.to_owned(),
+        }
     }
 }
 
@@ -69,39 +101,185 @@ enum Output {
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
-    let args = cli::App::parse();
-    let ctx = Context::new(
-        GithubClient::new(env::var("GITHUB_TOKEN").expect("GITHUB_TOKEN not set")),
-        args.global_opts,
-    );
+    let matches = cli::App::command().get_matches();
+    let mut args = cli::App::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let command = matches.subcommand_name().unwrap_or_default();
+
+    let mut config = Config::find(args.global_opts.config.as_deref())?;
+    apply_profile(&mut args.global_opts, &mut config)?;
+    let config = Arc::new(config);
+
+    let contexts = contexts(args.global_opts, &config, command)?;
+
+    match &args.command {
+        // these collect results across every host, so a single graph, PR or report is made
+        Command::Graph(args) => commands::dependency_graph(&contexts, args).await?,
+        Command::CreateSystemEntities(args) => {
+            commands::create_missing_system_entities(&contexts, args).await?
+        }
+        Command::ArgocdLint(args) => commands::argocd_lint(&contexts, args).await?,
+        command => {
+            for ctx in &contexts {
+                info!("running against {}", ctx.options.owners.join(", "));
+                run(ctx, command).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills in the options not given on the command line from the selected profile, and applies its
+/// settings to the config
+fn apply_profile(options: &mut GlobalOpts, config: &mut Config) -> Result<()> {
+    let name = options.profile.clone().or(config.default_profile.clone());
+
+    if let Some(name) = name {
+        let profile = config
+            .profiles
+            .remove(&name)
+            .context(format!("no profile {name} in the config file"))?;
+        info!("using profile {name}");
+        config.apply_profile(&profile);
+
+        if options.owners.is_empty() {
+            options.owners = profile.owners;
+        }
+        options.branch = options.branch.take().or(profile.branch);
+        options.repo = options.repo.take().or(profile.repo);
+    }
+
+    if options.owners.is_empty() && config.github.hosts.is_empty() {
+        bail!("no owners given, pass them or set them in a profile");
+    }
+
+    Ok(())
+}
+
+/// A context for the owners given on the command line or by the profile, and one for each of the
+/// configured hosts
+fn contexts(options: GlobalOpts, config: &Arc<Config>, command: &str) -> Result<Vec<Context>> {
+    let token = |name: &str| env::var(name).context(format!("{name} not set"));
+    let github = &config.github;
+    let url = options.github_url.as_deref().or(github.url.as_deref());
+    let cache = if options.no_cache {
+        None
+    } else {
+        HttpCache::from_config(&config.cache).map(Arc::new)
+    };
 
-    match args.command {
+    if let Some(cache) = &cache {
+        match cache.prune() {
+            Ok(0) => {}
+            Ok(pruned) => info!("deleted {pruned} old cache entries"),
+            Err(err) => log::warn!("could not clean up the cache: {err:#}"),
+        }
+    }
+
+    let mut contexts = vec![];
+
+    // the owners may all be on the configured hosts
+    if !options.owners.is_empty() {
+        contexts.push(Context::new(
+            GithubClient::new(token(&github.token_env)?, url, cache.clone())?,
+            options.clone(),
+            config.clone(),
+            command,
+        ));
+    }
+
+    for host in &github.hosts {
+        contexts.push(Context::new(
+            GithubClient::new(token(&host.token_env)?, host.url.as_deref(), cache.clone())?,
+            GlobalOpts {
+                owners: host.owners.clone(),
+                ..options.clone()
+            },
+            config.clone(),
+            command,
+        ));
+    }
+
+    Ok(contexts)
+}
+
+async fn run(ctx: &Context, command: &Command) -> Result<()> {
+    match command {
         Command::FindReplace(args) => {
-            commands::find_and_replace_in_org(&ctx, &args).await?;
+            commands::find_and_replace_in_org(ctx, args).await?;
         }
         Command::CreateCatalogFiles {} => {
-            commands::create_missing_catalog_files(&ctx).await?;
+            commands::create_missing_catalog_files(ctx).await?;
+        }
+        Command::EnrichCatalogFiles(args) => {
+            commands::enrich_catalog_files(ctx, args).await?;
         }
-        Command::EnrichCatalogFiles {} => {
-            commands::enrich_catalog_files(&ctx).await?;
+        Command::AddBadgesToReadmes(args) => {
+            commands::add_badges_to_readme(ctx, args).await?;
         }
-        Command::AddBadgesToReadmes {} => {
-            commands::add_badges_to_readme(&ctx).await?;
+        Command::ArgocdSet(args) => {
+            commands::argocd_set(ctx, args).await?;
+        }
+        Command::ReadmeSection(args) => {
+            commands::readme_section(ctx, args).await?;
+        }
+        Command::Graph(_) | Command::CreateSystemEntities(_) | Command::ArgocdLint(_) => {
+            unreachable!("run once across every context by main")
         }
     }
 
     Ok(())
 }
 
+/// Lists the unarchived repos of every owner, applying the `--repo` filter and `--skip` offset
+async fn find_repos(ctx: &Context) -> Result<Vec<Repository>> {
+    let mut repos = vec![];
+    for owner in &ctx.options.owners {
+        repos.extend(ctx.client.list_owner_repos(owner).await?);
+    }
+
+    let filter = ctx
+        .options
+        .repo
+        .as_ref()
+        .map(|filter| Regex::new(filter))
+        .transpose()
+        .context("parsing --repo filter")?;
+
+    let repos: Vec<_> = repos
+        .into_iter()
+        .filter(|repo| !matches!(repo.archived, Some(true)))
+        .filter(|repo| filter.as_ref().is_none_or(|re| re.is_match(&repo.name)))
+        .skip(ctx.options.skip.unwrap_or(0))
+        .collect();
+
+    info!("{} found repos to process", repos.len());
+
+    Ok(repos)
+}
+
+/// The login of the org or user a repo belongs to
+fn repo_owner(repo: &Repository) -> Result<&str> {
+    repo.owner
+        .as_ref()
+        .map(|owner| owner.login.as_str())
+        .context(format!("No owner for {}", repo.name))
+}
+
 async fn apply_changes(
     ctx: &Context,
     repo: &Repository,
     changes: ChangeSet,
-    title: String,
+    message: Message,
 ) -> Result<Output> {
-    let owner = &ctx.options.org;
+    let owner = repo_owner(repo)?;
     let should_write = ctx.options.write;
-    let branch_name = &ctx.options.branch;
+    let branch_name = &match (&ctx.options.branch, &ctx.config.branch_prefix) {
+        (Some(branch), _) => branch.to_owned(),
+        (None, Some(prefix)) => format!("{}/{}", prefix.trim_end_matches('/'), ctx.command),
+        (None, None) => "landscaper".to_owned(),
+    };
+    let pull_request = PullRequest::render(ctx, repo, &message, &changes)?;
     let repo_name = &repo.name;
 
     let default_branch = repo
@@ -110,6 +288,7 @@ async fn apply_changes(
         .context(format!("No default branch for {owner}/{repo_name}"))?;
 
     if !should_write {
+        info!("{owner}/{repo_name}: {}", pull_request.title);
         return Ok(Output::DryRun());
     }
 
@@ -131,20 +310,22 @@ async fn apply_changes(
         .await
         .context(format!("Creating remote branch {branch_name}"))?;
 
-    for change in changes.changes {
+    for (change, commit) in changes.changes.into_iter().zip(pull_request.commits) {
         match change {
             Change::CreateFile { path, content } => {
                 ctx.client
                     .repos(owner, repo_name)
-                    .create_file(&path, format!("chore: Create {}", &path), content)
+                    .create_file(&path, commit, content)
                     .branch(branch_name)
                     .send()
                     .await?;
             }
-            Change::UpdateFile { path, content, sha } => {
+            Change::UpdateFile {
+                path, content, sha, ..
+            } => {
                 ctx.client
                     .repos(owner, repo_name)
-                    .update_file(&path, format!("chore: Update {}", &path), content, &sha)
+                    .update_file(&path, commit, content, &sha)
                     .branch(branch_name)
                     .send()
                     .await?;
@@ -155,7 +336,8 @@ async fn apply_changes(
     let pr = ctx
         .client
         .pulls(owner, repo_name)
-        .create(title, branch_name, default_branch)
+        .create(pull_request.title, branch_name, default_branch)
+        .body(pull_request.body)
         .send()
         .await?;
 
@@ -0,0 +1,107 @@
+use crate::{repo_owner, Context};
+use anyhow::Result;
+use log::{debug, warn};
+use octocrab::models::Repository;
+
+/// Where GitHub looks for a CODEOWNERS file, in order of precedence
+const CODEOWNERS_PATHS: &[&str] = &[".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+/// Team permissions that make a team an owner of a repo, in order of preference
+const OWNER_PERMISSIONS: &[&str] = &["admin", "maintain"];
+
+/// Works out the backstage owner ref of a repo, from its CODEOWNERS file or failing that the
+/// team with admin or maintain permission on it
+pub(crate) async fn infer_owner(ctx: &Context, repo: &Repository) -> Result<Option<String>> {
+    let owner = repo_owner(repo)?;
+
+    for path in CODEOWNERS_PATHS {
+        let Ok(file) = ctx.client.get_file_content(owner, &repo.name, path).await else {
+            continue;
+        };
+
+        debug!("{} has {}", &repo.name, path);
+
+        if let Some(code_owner) = file.decoded_content().as_deref().and_then(default_code_owner) {
+            return Ok(Some(owner_ref(ctx, code_owner)));
+        }
+    }
+
+    // the token may not be allowed to see the teams, and user repos have none
+    let teams = match ctx
+        .client
+        .repos(owner, &repo.name)
+        .list_teams()
+        .per_page(100)
+        .send()
+        .await
+    {
+        Ok(teams) => ctx.client.all_pages(teams).await,
+        Err(err) => Err(err),
+    };
+
+    let teams = match teams {
+        Ok(teams) => teams,
+        Err(err) => {
+            warn!("could not list the teams of {owner}/{}: {err}", repo.name);
+            return Ok(None);
+        }
+    };
+
+    Ok(OWNER_PERMISSIONS.iter().find_map(|permission| {
+        teams
+            .iter()
+            .find(|team| &team.permission == permission)
+            .map(|team| team_ref(ctx, &team.slug))
+    }))
+}
+
+/// The first owner of the last catch-all rule in a CODEOWNERS file. The last matching rule wins,
+/// so a catch-all rule without owners leaves the repo unowned.
+fn default_code_owner(codeowners: &str) -> Option<&str> {
+    let mut rule = codeowners
+        .lines()
+        .rev()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .map(str::split_whitespace)
+        .find(|parts| matches!(parts.clone().next(), Some("*" | "/" | "/*" | "/**")))?;
+
+    rule.find(|owner| owner.starts_with('@'))
+}
+
+/// Converts a CODEOWNERS `@org/team` or `@user` into a backstage owner ref
+fn owner_ref(ctx: &Context, code_owner: &str) -> String {
+    let code_owner = code_owner.trim_start_matches('@');
+
+    match code_owner.split_once('/') {
+        Some((_, team)) => team_ref(ctx, team),
+        None => format!("user:{code_owner}"),
+    }
+}
+
+fn team_ref(ctx: &Context, slug: &str) -> String {
+    ctx.config
+        .owners
+        .teams
+        .get(slug)
+        .cloned()
+        .unwrap_or_else(|| format!("group:{slug}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_first_owner_of_last_catch_all_rule() {
+        let codeowners =
+            "# owners\n* @acme/old\n/docs/ @acme/docs\n/** @acme/web @jane # web team\n";
+        assert_eq!(default_code_owner(codeowners), Some("@acme/web"));
+    }
+
+    #[test]
+    fn catch_all_without_owners_leaves_repo_unowned() {
+        assert_eq!(default_code_owner("* @acme/web\n*\n"), None);
+        assert_eq!(default_code_owner("/docs/ @acme/docs\n"), None);
+        assert_eq!(default_code_owner("* jane@acme.com\n"), None);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{
+    repo_owner,
+    template::{self, Vars},
+    Change, ChangeSet, Context,
+};
+use anyhow::{Context as anyhowContext, Result};
+use octocrab::models::Repository;
+use prettydiff::basic::DiffOp;
+use regex::Regex;
+
+/// Conventional Commit types, a message starting with any other word is read as a plain summary
+const KINDS: [&str; 11] = [
+    "build", "chore", "ci", "docs", "feat", "fix", "perf", "refactor", "revert", "style", "test",
+];
+
+/// What a PR does, as the parts of a Conventional Commit
+#[derive(Debug, PartialEq)]
+pub(crate) struct Message {
+    /// Conventional Commit type, eg `chore` or `docs`
+    pub kind: String,
+    pub scope: Option<String>,
+    pub summary: String,
+    /// Whether the change is marked as breaking with a `!`
+    pub breaking: bool,
+    /// Marker for changes CI can be skipped for, eg when they only touch docs
+    pub skip_ci: Option<SkipCi>,
+    /// A message given on the command line, used as the subject as is
+    pub verbatim: Option<String>,
+}
+
+/// Where a message puts its skip CI marker, and the marker used when the config doesn't set one
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum SkipCi {
+    Prefix(&'static str),
+    Suffix(&'static str),
+}
+
+impl Message {
+    pub fn new(kind: &str, summary: impl Into<String>) -> Self {
+        Self {
+            kind: kind.to_owned(),
+            scope: None,
+            summary: summary.into(),
+            breaking: false,
+            skip_ci: None,
+            verbatim: None,
+        }
+    }
+
+    /// Puts a skip CI marker before the title, eg `[ci-skip] docs: Update badges in readme`
+    pub fn skip_ci_prefix(mut self, marker: &'static str) -> Self {
+        self.skip_ci = Some(SkipCi::Prefix(marker));
+        self
+    }
+
+    /// Puts a skip CI marker after the title, eg `chore: Add catalog-info.yaml [no-ci]`
+    pub fn skip_ci_suffix(mut self, marker: &'static str) -> Self {
+        self.skip_ci = Some(SkipCi::Suffix(marker));
+        self
+    }
+
+    /// Reads a message given on the command line, which is used as the subject as is. Its parts
+    /// are read from it when it's a Conventional Commit such as `fix(deps): Bump lodash`, the
+    /// default type is used otherwise.
+    pub fn parse(message: &str, default_kind: &str) -> Self {
+        let re = Regex::new(r"^(\w+)(?:\(([^)]+)\))?(!)?:\s*(.+)$").unwrap();
+
+        let parsed = match re.captures(message) {
+            Some(captures) if KINDS.contains(&captures[1].to_lowercase().as_str()) => Self {
+                kind: captures[1].to_owned(),
+                scope: captures.get(2).map(|scope| scope.as_str().to_owned()),
+                summary: captures[4].to_owned(),
+                breaking: captures.get(3).is_some(),
+                ..Self::new(default_kind, "")
+            },
+            _ => Self::new(default_kind, message),
+        };
+
+        Self {
+            verbatim: Some(message.to_owned()),
+            ..parsed
+        }
+    }
+
+    /// The Conventional Commit header, eg `docs(readme): Update badges`
+    fn subject(&self) -> String {
+        if let Some(message) = &self.verbatim {
+            return message.to_owned();
+        }
+
+        let scope = match &self.scope {
+            Some(scope) => format!("({scope})"),
+            None => String::new(),
+        };
+        let breaking = if self.breaking { "!" } else { "" };
+
+        format!("{}{scope}{breaking}: {}", self.kind, self.summary)
+    }
+}
+
+/// The title, body and commit messages of a PR, rendered from the templates in the config
+pub(crate) struct PullRequest {
+    pub title: String,
+    pub body: String,
+    /// One commit message per change, in order
+    pub commits: Vec<String>,
+}
+
+impl PullRequest {
+    pub fn render(
+        ctx: &Context,
+        repo: &Repository,
+        message: &Message,
+        changes: &ChangeSet,
+    ) -> Result<Self> {
+        let config = &ctx.config.pull_requests;
+        let vars = vars(ctx, repo, message, changes)?;
+        let full_name = &vars["full_name"];
+
+        let render = |template: &str, vars: &Vars, what: &str| {
+            template::render(template, vars)
+                .map(|rendered| rendered.trim().to_owned())
+                .context(format!("{what} template uses a variable {full_name} doesn't have"))
+        };
+
+        let title = match config.titles.get(&ctx.command) {
+            Some(title) => title,
+            None => &config.title,
+        };
+
+        let commits = changes
+            .changes
+            .iter()
+            .map(|change| {
+                let (action, path) = match change {
+                    Change::CreateFile { path, .. } => ("Create", path),
+                    Change::UpdateFile { path, .. } => ("Update", path),
+                };
+
+                let mut vars = vars.clone();
+                vars.insert("action", action.to_owned());
+                vars.insert("path", path.to_owned());
+                render(&config.commit, &vars, "commit message")
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            title: render(title, &vars, "PR title")?,
+            body: render(&config.body, &vars, "PR body")?,
+            commits,
+        })
+    }
+}
+
+fn vars(ctx: &Context, repo: &Repository, message: &Message, changes: &ChangeSet) -> Result<Vars> {
+    let config = &ctx.config.pull_requests;
+    let campaign = ctx.options.campaign.as_ref().or(config.campaign.as_ref());
+
+    let (skip_ci_prefix, skip_ci_suffix) = match message.skip_ci {
+        Some(SkipCi::Prefix(marker)) => (config.skip_ci_marker(&repo.name, marker)?, ""),
+        Some(SkipCi::Suffix(marker)) => ("", config.skip_ci_marker(&repo.name, marker)?),
+        None => ("", ""),
+    };
+
+    let mut list = vec![];
+    let (mut additions, mut deletions) = (0, 0);
+
+    for change in &changes.changes {
+        let (line, (added, deleted)) = match change {
+            Change::CreateFile { path, content } => {
+                (format!("- Create `{path}`"), (content.lines().count(), 0))
+            }
+            Change::UpdateFile {
+                path,
+                content,
+                original,
+                ..
+            } => (format!("- Update `{path}`"), line_stats(original, content)),
+        };
+
+        list.push(line);
+        additions += added;
+        deletions += deleted;
+    }
+
+    Ok(Vars::from([
+        ("repo", repo.name.to_owned()),
+        ("owner", repo_owner(repo)?.to_owned()),
+        ("full_name", repo.full_name.clone().unwrap_or_default()),
+        ("default_branch", repo.default_branch.clone().unwrap_or_default()),
+        ("command", ctx.command.to_owned()),
+        ("campaign", campaign.cloned().unwrap_or_default()),
+        ("type", message.kind.to_owned()),
+        ("scope", message.scope.clone().unwrap_or_default()),
+        ("summary", message.summary.to_owned()),
+        ("subject", message.subject()),
+        ("skip_ci", format!("{skip_ci_prefix}{skip_ci_suffix}")),
+        ("skip_ci_prefix", skip_ci_prefix.to_owned()),
+        ("skip_ci_suffix", skip_ci_suffix.to_owned()),
+        ("changes", list.join("\n")),
+        ("files", changes.changes.len().to_string()),
+        ("additions", additions.to_string()),
+        ("deletions", deletions.to_string()),
+    ]))
+}
+
+/// The number of lines added and removed between two versions of a file
+fn line_stats(original: &str, content: &str) -> (usize, usize) {
+    let original: Vec<_> = original.lines().collect();
+    let content: Vec<_> = content.lines().collect();
+
+    prettydiff::basic::diff(&original, &content)
+        .iter()
+        .fold((0, 0), |(added, removed), op| match op {
+            DiffOp::Insert(lines) => (added + lines.len(), removed),
+            DiffOp::Remove(lines) => (added, removed + lines.len()),
+            DiffOp::Replace(old, new) => (added + new.len(), removed + old.len()),
+            DiffOp::Equal(_) => (added, removed),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_conventional_commits() {
+        let message = Message::parse("fix(deps)!: Bump lodash", "chore");
+
+        assert_eq!(message.kind, "fix");
+        assert_eq!(message.scope.as_deref(), Some("deps"));
+        assert_eq!(message.summary, "Bump lodash");
+        assert!(message.breaking);
+        assert_eq!(message.subject(), "fix(deps)!: Bump lodash");
+        assert_eq!(Message::parse("docs: Fix typo", "chore").subject(), "docs: Fix typo");
+    }
+
+    #[test]
+    fn reads_other_messages_as_summary() {
+        let message = Message::parse("Note: run the migration first", "chore");
+
+        assert_eq!(message.kind, "chore");
+        assert_eq!(message.summary, "Note: run the migration first");
+        assert_eq!(message.subject(), "Note: run the migration first");
+        assert_eq!(Message::parse("Bump lodash", "docs").subject(), "Bump lodash");
+        assert_eq!(Message::new("docs", "Bump lodash").subject(), "docs: Bump lodash");
+    }
+
+    #[test]
+    fn counts_changed_lines() {
+        assert_eq!(line_stats("a\nb\nc\n", "a\nB\nc\nd\n"), (2, 1));
+        assert_eq!(line_stats("a\n", "a\n"), (0, 0));
+    }
+}
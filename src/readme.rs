@@ -0,0 +1,468 @@
+use crate::{backstage::Component, github::GithubClient, repo_files::RepoBlobs};
+use anyhow::Result;
+use log::warn;
+use octocrab::models::Repository;
+use regex::Regex;
+
+/// Directories GitHub looks for a README in, in order of precedence
+const README_DIRS: &[&str] = &[".github/", "", "docs/"];
+
+/// README extensions, in order of preference when a directory has several
+const README_EXTENSIONS: &[&str] = &[".md", ".markdown", ".rst", ".adoc", ".asciidoc", ".txt", ""];
+
+/// The markup language of a README
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Markup {
+    Markdown,
+    ReStructuredText,
+    AsciiDoc,
+    PlainText,
+}
+
+impl Markup {
+    pub fn from_path(path: &str) -> Self {
+        let path = path.to_lowercase();
+
+        if path.ends_with(".md") || path.ends_with(".markdown") {
+            Markup::Markdown
+        } else if path.ends_with(".rst") {
+            Markup::ReStructuredText
+        } else if path.ends_with(".adoc") || path.ends_with(".asciidoc") {
+            Markup::AsciiDoc
+        } else {
+            Markup::PlainText
+        }
+    }
+
+    /// A comment line, None for plain text which has no comments
+    pub fn comment(self, text: &str) -> Option<String> {
+        match self {
+            Markup::Markdown => Some(format!("<!-- {text} -->")),
+            Markup::ReStructuredText => Some(format!(".. {text}")),
+            Markup::AsciiDoc => Some(format!("// {text}")),
+            Markup::PlainText => None,
+        }
+    }
+
+    /// The number of lines taken by the heading starting at the line, if there is one. The header
+    /// of an AsciiDoc document, its title plus attribute lines, counts as a single heading.
+    pub fn heading_at(self, lines: &[&str], idx: usize) -> Option<usize> {
+        let line = lines[idx];
+        let next = lines.get(idx + 1).copied().unwrap_or_default();
+
+        match self {
+            Markup::Markdown => {
+                let setext = Regex::new(r"^(=+|-+)\s*$").unwrap();
+
+                if line.starts_with('#') {
+                    Some(1)
+                } else if !line.trim().is_empty() && setext.is_match(next) {
+                    Some(2)
+                } else {
+                    None
+                }
+            }
+            Markup::ReStructuredText => {
+                let is_adornment = |line: &str| {
+                    let mut chars = line.trim_end().chars();
+                    chars.next().is_some_and(|first| {
+                        first.is_ascii_punctuation() && chars.all(|c| c == first)
+                    })
+                };
+
+                if is_adornment(line)
+                    && !next.trim().is_empty()
+                    && lines.get(idx + 2).is_some_and(|under| is_adornment(under))
+                {
+                    Some(3)
+                } else if !line.trim().is_empty()
+                    && !is_adornment(line)
+                    && is_adornment(next)
+                    && next.trim_end().len() >= line.trim_end().len()
+                {
+                    Some(2)
+                } else {
+                    None
+                }
+            }
+            Markup::AsciiDoc => line.starts_with("= ").then(|| {
+                lines[idx..]
+                    .iter()
+                    .take_while(|line| !line.trim().is_empty())
+                    .count()
+            }),
+            Markup::PlainText => None,
+        }
+    }
+}
+
+/// Finds the README of a repo among its paths the way GitHub does, in any case and in `.github/`,
+/// the root or `docs/`
+pub(crate) fn find_readme(paths: &[String]) -> Option<&str> {
+    README_DIRS.iter().find_map(|dir| {
+        README_EXTENSIONS.iter().find_map(|extension| {
+            let name = format!("{dir}readme{extension}");
+            paths
+                .iter()
+                .find(|path| path.eq_ignore_ascii_case(&name))
+                .map(String::as_str)
+        })
+    })
+}
+
+/// Fetches the README of every repo along with the files at `paths`, with GraphQL queries listing
+/// the README directories and then getting the README found. Returns the path of the README too,
+/// or an error for repos that couldn't be fetched.
+pub(crate) async fn fetch_readmes(
+    client: &GithubClient,
+    repos: &[Repository],
+    paths: &[&str],
+) -> Result<Vec<Result<(Option<String>, RepoBlobs)>>> {
+    let paths: Vec<&str> = README_DIRS
+        .iter()
+        .map(|dir| dir.trim_end_matches('/'))
+        .chain(paths.iter().copied())
+        .collect();
+    let listed = client.get_files_batch(repos, &paths).await?;
+
+    let readme_paths: Vec<_> = listed
+        .iter()
+        .map(|blobs| {
+            blobs
+                .as_ref()
+                .ok()
+                .and_then(|blobs| find_readme(&blobs.listed))
+                .map(str::to_owned)
+        })
+        .collect();
+    let requests: Vec<_> = repos
+        .iter()
+        .zip(&readme_paths)
+        .map(|(repo, path)| (repo, path.iter().cloned().collect()))
+        .collect();
+    let readmes = client.get_blobs_batch(&requests).await?;
+
+    Ok(readme_paths
+        .into_iter()
+        .zip(listed.into_iter().zip(readmes))
+        .map(|(path, (blobs, readme))| {
+            let mut blobs = blobs?;
+            blobs.extend(readme?);
+            Ok((path, blobs))
+        })
+        .collect())
+}
+
+/// A README.md with just the name and description of the entity, for repos without one
+pub(crate) fn minimal(component: &Component, repo: &Repository) -> String {
+    let description = Some(component.metadata.description.as_str())
+        .filter(|description| !description.is_empty())
+        .or(repo.description.as_deref())
+        .unwrap_or_default();
+
+    let mut readme = format!("# {}", component.metadata.name);
+    if !description.is_empty() {
+        readme.push_str(&format!("\n\n{description}"));
+    }
+    readme + "\n"
+}
+
+/// The number of lines taken by YAML front matter at the start of a Markdown document
+pub(crate) fn front_matter_len(lines: &[&str]) -> usize {
+    if lines.first() != Some(&"---") {
+        return 0;
+    }
+
+    lines
+        .iter()
+        .skip(1)
+        .position(|line| *line == "---")
+        .map_or(0, |end| end + 2)
+}
+
+/// A heading in a Markdown document
+struct Heading {
+    line: usize,
+    level: usize,
+    text: String,
+}
+
+/// The ATX and setext headings of a Markdown document, ignoring anything in code blocks
+fn headings(lines: &[&str]) -> Vec<Heading> {
+    let atx = Regex::new(r"^(#{1,6})\s+(.*?)[\s#]*$").unwrap();
+    let setext = Regex::new(r"^(=+|-+)\s*$").unwrap();
+    let mut headings = vec![];
+    let mut fence: Option<&str> = None;
+
+    let start = front_matter_len(lines);
+
+    for idx in start..lines.len() {
+        let line = lines[idx];
+        let trimmed = line.trim_start();
+
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            continue;
+        }
+
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fence = Some(&trimmed[..3]);
+        } else if let Some(captures) = atx.captures(line) {
+            headings.push(Heading {
+                line: idx,
+                level: captures[1].len(),
+                text: captures[2].to_owned(),
+            });
+        } else if !line.trim().is_empty()
+            && (idx == start || lines[idx - 1].trim().is_empty())
+            && lines.get(idx + 1).is_some_and(|next| setext.is_match(next))
+        {
+            headings.push(Heading {
+                line: idx,
+                level: if lines[idx + 1].starts_with('=') { 1 } else { 2 },
+                text: line.trim().to_owned(),
+            });
+        }
+    }
+
+    headings
+}
+
+/// Where a section is in a document, as a range of lines
+enum SectionLocation {
+    /// Under the heading with this text, up to the next heading of the same or a higher level
+    Heading(String),
+    /// Between `<!-- landscaper:name -->` and `<!-- /landscaper:name -->` comments
+    Marker(String),
+}
+
+/// A section of a Markdown README, found by heading or marker comments
+pub(crate) struct Section {
+    location: SectionLocation,
+    level: usize,
+}
+
+impl Section {
+    pub fn heading(text: impl Into<String>, level: usize) -> Self {
+        Self {
+            location: SectionLocation::Heading(text.into()),
+            level,
+        }
+    }
+
+    pub fn marker(name: impl Into<String>) -> Self {
+        Self {
+            location: SectionLocation::Marker(name.into()),
+            level: 0,
+        }
+    }
+
+    fn markers(name: &str) -> (String, String) {
+        (
+            format!("<!-- landscaper:{name} -->"),
+            format!("<!-- /landscaper:{name} -->"),
+        )
+    }
+
+    /// The lines the section takes up, if the document has it
+    fn find(&self, lines: &[&str]) -> Option<std::ops::Range<usize>> {
+        match &self.location {
+            SectionLocation::Heading(text) => {
+                let headings = headings(lines);
+                let position = headings
+                    .iter()
+                    .position(|heading| heading.text.eq_ignore_ascii_case(text))?;
+                let heading = &headings[position];
+                let end = headings[position + 1..]
+                    .iter()
+                    .find(|next| next.level <= heading.level)
+                    .map_or(lines.len(), |next| next.line);
+
+                Some(heading.line..end)
+            }
+            SectionLocation::Marker(name) => {
+                let (start_marker, end_marker) = Self::markers(name);
+                let start = lines.iter().position(|line| line.trim() == start_marker)?;
+
+                let end = lines[start..].iter().position(|line| line.trim() == end_marker);
+                if let Some(end) = end {
+                    return Some(start..start + end + 1);
+                }
+
+                // a start marker whose end marker was deleted by hand runs up to the next heading
+                warn!("{end_marker} is missing, replacing up to the next heading");
+                let end = headings(lines)
+                    .iter()
+                    .find(|heading| heading.line > start)
+                    .map_or(lines.len(), |heading| heading.line);
+
+                Some(start..end)
+            }
+        }
+    }
+
+    /// The lines of the section with the given content
+    fn render(&self, lines: &[&str], content: &str) -> Vec<String> {
+        let content = content.trim().lines().map(str::to_owned);
+
+        match &self.location {
+            SectionLocation::Heading(text) => {
+                // an existing heading keeps its level and spelling
+                let heading = match headings(lines)
+                    .into_iter()
+                    .find(|heading| heading.text.eq_ignore_ascii_case(text))
+                {
+                    Some(heading) if lines[heading.line].starts_with('#') => {
+                        lines[heading.line].to_owned()
+                    }
+                    Some(heading) => format!("{} {}", "#".repeat(heading.level), heading.text),
+                    None => format!("{} {text}", "#".repeat(self.level)),
+                };
+
+                [heading, String::new()].into_iter().chain(content).collect()
+            }
+            SectionLocation::Marker(name) => {
+                let (start_marker, end_marker) = Self::markers(name);
+                std::iter::once(start_marker)
+                    .chain(content)
+                    .chain(std::iter::once(end_marker))
+                    .collect()
+            }
+        }
+    }
+
+    /// Replaces the content of the section, or adds the section after the section with the
+    /// `after` heading or at the end of the document when it doesn't have it yet
+    pub fn upsert(&self, readme: &str, content: &str, after: Option<&str>) -> String {
+        let lines: Vec<_> = readme.lines().collect();
+        let rendered = self.render(&lines, content);
+
+        let range = self.find(&lines).unwrap_or_else(|| {
+            let position = after
+                .and_then(|after| Section::heading(after, 0).find(&lines))
+                .map_or(lines.len(), |range| range.end);
+            position..position
+        });
+
+        with_line_endings(readme, splice(&lines, range, rendered))
+    }
+
+    /// Takes the section out of the document, returning it unchanged when it doesn't have it
+    pub fn remove(&self, readme: &str) -> String {
+        let lines: Vec<_> = readme.lines().collect();
+
+        match self.find(&lines) {
+            Some(range) => with_line_endings(readme, splice(&lines, range, vec![])),
+            None => readme.to_owned(),
+        }
+    }
+}
+
+/// Converts the line endings of the updated text back to CRLF when the original used them
+pub(crate) fn with_line_endings(original: &str, updated: String) -> String {
+    if original.contains("\r\n") {
+        updated.replace('\n', "\r\n")
+    } else {
+        updated
+    }
+}
+
+/// Replaces a range of lines, keeping a single blank line around the replacement
+fn splice(lines: &[&str], range: std::ops::Range<usize>, replacement: Vec<String>) -> String {
+    let is_blank = |line: &&&str| line.trim().is_empty();
+
+    let before = &lines[..range.start];
+    let before = &before[..before.len() - before.iter().rev().take_while(is_blank).count()];
+    let after = &lines[range.end..];
+    let after = &after[after.iter().take_while(is_blank).count()..];
+
+    let mut spliced: Vec<String> = before.iter().map(|line| line.to_string()).collect();
+    for part in [replacement, after.iter().map(|line| line.to_string()).collect()] {
+        if part.is_empty() {
+            continue;
+        }
+        if !spliced.is_empty() {
+            spliced.push(String::new());
+        }
+        spliced.extend(part);
+    }
+
+    spliced.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const README: &str = "# Title\n\n## Usage\n\nold\n\n### Flags\n\n-v\n\n## License\n\nMIT\n";
+
+    #[test]
+    fn replaces_heading_section_with_subsections() {
+        assert_eq!(
+            Section::heading("usage", 2).upsert(README, "new\n", None),
+            "# Title\n\n## Usage\n\nnew\n\n## License\n\nMIT\n"
+        );
+    }
+
+    #[test]
+    fn adds_heading_section_after_another() {
+        let readme = "# Title\n\n## Usage\n\nrun\n\n## License\n";
+        assert_eq!(
+            Section::heading("Install", 2).upsert(readme, "cargo install", Some("Usage")),
+            "# Title\n\n## Usage\n\nrun\n\n## Install\n\ncargo install\n\n## License\n"
+        );
+    }
+
+    #[test]
+    fn ignores_headings_in_code_blocks() {
+        let readme = "# Title\n\n```sh\n# Usage\n```\n";
+        assert_eq!(
+            Section::heading("Usage", 2).upsert(readme, "run", None),
+            "# Title\n\n```sh\n# Usage\n```\n\n## Usage\n\nrun\n"
+        );
+    }
+
+    #[test]
+    fn replaces_marker_section() {
+        let readme = "# Title\n\n<!-- landscaper:ci -->\nold\n<!-- /landscaper:ci -->\n\nMore\n";
+        assert_eq!(
+            Section::marker("ci").upsert(readme, "new", None),
+            "# Title\n\n<!-- landscaper:ci -->\nnew\n<!-- /landscaper:ci -->\n\nMore\n"
+        );
+        assert_eq!(Section::marker("ci").remove(readme), "# Title\n\nMore\n");
+    }
+
+    #[test]
+    fn replaces_lone_start_marker_up_to_next_heading() {
+        let readme = "# Title\n\n<!-- landscaper:ci -->\nold\n\n## Next\n";
+        assert_eq!(
+            Section::marker("ci").upsert(readme, "new", None),
+            "# Title\n\n<!-- landscaper:ci -->\nnew\n<!-- /landscaper:ci -->\n\n## Next\n"
+        );
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        let readme = "# Title\r\n\r\n## Usage\r\n\r\nold\r\n";
+        assert_eq!(
+            Section::heading("Usage", 2).upsert(readme, "new\nlines", None),
+            "# Title\r\n\r\n## Usage\r\n\r\nnew\r\nlines\r\n"
+        );
+        assert_eq!(Section::heading("Usage", 2).remove(readme), "# Title\r\n");
+    }
+
+    #[test]
+    fn leaves_readme_without_section_alone() {
+        assert_eq!(Section::heading("Missing", 2).remove(README), README);
+        assert_eq!(Section::marker("missing").remove(README), README);
+    }
+
+    #[test]
+    fn skips_front_matter() {
+        let lines = ["---", "title: x", "---", "# Title"];
+        assert_eq!(front_matter_len(&lines), 3);
+        assert_eq!(front_matter_len(&lines[3..]), 0);
+    }
+}
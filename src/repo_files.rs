@@ -0,0 +1,82 @@
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::{collections::BTreeMap, io::Read};
+
+/// Files larger than this are left out of a snapshot
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// The text files of a repo at a point in time, keyed by path
+#[derive(Default)]
+pub(crate) struct RepoFiles {
+    files: BTreeMap<String, String>,
+}
+
+impl RepoFiles {
+    /// Reads a repo tarball as served by GitHub, which nests everything in a `owner-repo-sha/` dir.
+    /// Binary and large files are skipped.
+    pub fn from_tarball(tarball: &[u8]) -> Result<Self> {
+        let mut archive = tar::Archive::new(GzDecoder::new(tarball));
+        let mut files = BTreeMap::new();
+
+        for entry in archive.entries().context("reading tarball")? {
+            let mut entry = entry.context("reading tarball entry")?;
+
+            if !entry.header().entry_type().is_file() || entry.size() > MAX_FILE_SIZE {
+                continue;
+            }
+
+            let path = entry.path().context("reading tarball entry path")?;
+            let Some((_, path)) = path.to_str().and_then(|path| path.split_once('/')) else {
+                continue;
+            };
+            let path = path.to_owned();
+
+            let mut content = vec![];
+            entry
+                .read_to_end(&mut content)
+                .context(format!("reading {path} from tarball"))?;
+
+            if let Ok(content) = String::from_utf8(content) {
+                files.insert(path, content);
+            }
+        }
+
+        Ok(Self { files })
+    }
+
+    pub fn get(&self, path: &str) -> Option<&str> {
+        self.files.get(path).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.files
+            .iter()
+            .map(|(path, content)| (path.as_str(), content.as_str()))
+    }
+}
+
+/// A file with the blob sha needed to update it
+pub(crate) struct Blob {
+    pub sha: String,
+    pub text: String,
+}
+
+/// Files and directory listings of a repo fetched in a GraphQL batch
+#[derive(Default)]
+pub(crate) struct RepoBlobs {
+    /// The requested files the repo has, keyed by path
+    pub blobs: BTreeMap<String, Blob>,
+    /// Paths of the files in the requested directories
+    pub listed: Vec<String>,
+}
+
+impl RepoBlobs {
+    pub fn get(&self, path: &str) -> Option<&Blob> {
+        self.blobs.get(path)
+    }
+
+    pub fn extend(&mut self, other: RepoBlobs) {
+        self.blobs.extend(other.blobs);
+        self.listed.extend(other.listed);
+    }
+}
@@ -0,0 +1,48 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use octocrab::models::Repository;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Deserialize)]
+struct MappingRow {
+    repo: String,
+    system: String,
+}
+
+/// Assigns repos to systems from the mapping file, the catalog rules or their Argo CD project
+pub(crate) struct SystemRules<'a> {
+    config: &'a Config,
+    mapping: BTreeMap<String, String>,
+}
+
+impl<'a> SystemRules<'a> {
+    pub fn load(config: &'a Config) -> Result<Self> {
+        let mut mapping = BTreeMap::new();
+
+        if let Some(path) = &config.systems.mapping_file {
+            let mut reader = csv::Reader::from_path(path)
+                .context(format!("reading system mapping {}", path.display()))?;
+
+            for row in reader.deserialize() {
+                let row: MappingRow =
+                    row.context(format!("parsing system mapping {}", path.display()))?;
+                mapping.insert(row.repo, row.system);
+            }
+        }
+
+        Ok(Self { config, mapping })
+    }
+
+    pub fn system_for(&self, repo: &Repository, argocd_project: Option<&str>) -> Option<String> {
+        self.mapping
+            .get(&repo.name)
+            .cloned()
+            .or_else(|| self.config.catalog.defaults_for(repo).system)
+            .or_else(|| {
+                argocd_project
+                    .and_then(|project| self.config.systems.argocd_projects.get(project))
+                    .cloned()
+            })
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{config::TagsConfig, repo_owner, Context};
+use anyhow::{Context as anyhowContext, Result};
+use octocrab::models::Repository;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Manifest files at the root of a repo that dependencies are read from
+pub(crate) const MANIFESTS: &[&str] = &[
+    "Cargo.toml",
+    "package.json",
+    "go.mod",
+    "composer.json",
+    "pom.xml",
+    "Dockerfile",
+];
+
+/// Tags for the languages making up a large enough share of the repo
+pub(crate) async fn language_tags(
+    ctx: &Context,
+    repo: &Repository,
+    config: &TagsConfig,
+) -> Result<BTreeSet<String>> {
+    let owner = repo_owner(repo)?;
+    let languages: BTreeMap<String, u64> = ctx
+        .client
+        .get_cached(&format!("/repos/{owner}/{}/languages", repo.name))
+        .await
+        .context(format!("getting languages for {owner}/{}", repo.name))?;
+
+    let total: u64 = languages.values().sum();
+
+    Ok(languages
+        .into_iter()
+        .filter(|(_, bytes)| *bytes as f64 >= total as f64 * config.min_language_share)
+        .map(|(language, _)| {
+            config
+                .languages
+                .get(&language)
+                .cloned()
+                .unwrap_or_else(|| tag(&language))
+        })
+        .collect())
+}
+
+/// Tags for the frameworks and runtimes a manifest file pulls in
+pub(crate) fn manifest_tags(path: &str, content: &str, config: &TagsConfig) -> BTreeSet<String> {
+    let dependencies = match path {
+        "Cargo.toml" => cargo_dependencies(content),
+        "package.json" => json_dependencies(content, &["dependencies", "devDependencies"]),
+        "composer.json" => json_dependencies(content, &["require", "require-dev"]),
+        "go.mod" => go_dependencies(content),
+        "pom.xml" => maven_dependencies(content),
+        "Dockerfile" => {
+            return docker_images(content)
+                .iter()
+                .filter_map(|image| config.images.get(image).cloned())
+                .chain(["docker".to_owned()])
+                .collect();
+        }
+        _ => vec![],
+    };
+
+    dependencies
+        .iter()
+        .filter_map(|dependency| config.dependencies.get(dependency).cloned())
+        .collect()
+}
+
+/// Backstage tags are lowercase words separated by dashes
+fn tag(name: &str) -> String {
+    name.to_lowercase().split_whitespace().collect::<Vec<_>>().join("-")
+}
+
+fn cargo_dependencies(content: &str) -> Vec<String> {
+    let Ok(manifest) = content.parse::<toml::Table>() else {
+        return vec![];
+    };
+
+    ["dependencies", "dev-dependencies"]
+        .iter()
+        .filter_map(|section| manifest.get(*section).and_then(|deps| deps.as_table()))
+        .flat_map(|deps| deps.keys().cloned())
+        .collect()
+}
+
+fn json_dependencies(content: &str, sections: &[&str]) -> Vec<String> {
+    let Ok(manifest) = serde_json::from_str::<serde_json::Value>(content) else {
+        return vec![];
+    };
+
+    sections
+        .iter()
+        .filter_map(|section| manifest[section].as_object())
+        .flat_map(|deps| deps.keys().cloned())
+        .collect()
+}
+
+fn go_dependencies(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|line| line.trim().trim_start_matches("require").trim())
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|module| module.contains('.') && module.contains('/'))
+        .map(str::to_owned)
+        .collect()
+}
+
+fn maven_dependencies(content: &str) -> Vec<String> {
+    let re = Regex::new(r"<artifactId>\s*([^<\s]+)\s*</artifactId>").unwrap();
+
+    re.captures_iter(content)
+        .map(|captures| captures[1].to_owned())
+        .collect()
+}
+
+/// Names of the base images, without registry or tag, eg `node` for `FROM node:18-alpine AS build`
+fn docker_images(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some(from) if from.eq_ignore_ascii_case("FROM") => words
+                    .find(|word| !word.starts_with("--"))
+                    .and_then(|image| image.rsplit('/').next())
+                    .and_then(|image| image.split([':', '@']).next())
+                    .map(str::to_owned),
+                _ => None,
+            }
+        })
+        .collect()
+}
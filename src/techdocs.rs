@@ -0,0 +1,54 @@
+use crate::{backstage::Component, Change};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+pub(crate) const TECHDOCS_REF: &str = "backstage.io/techdocs-ref";
+
+/// Whether the repo already has a mkdocs site that TechDocs can build
+pub(crate) fn has_techdocs(paths: &[String]) -> bool {
+    let has_config = paths
+        .iter()
+        .any(|path| path == "mkdocs.yml" || path == "mkdocs.yaml");
+    let has_docs = paths.iter().any(|path| path.starts_with("docs/"));
+
+    has_config && has_docs
+}
+
+/// The parts of a mkdocs.yml the scaffold sets
+#[derive(Serialize)]
+struct MkDocs<'a> {
+    site_name: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    site_description: &'a str,
+    nav: Vec<BTreeMap<&'a str, &'a str>>,
+    plugins: Vec<&'a str>,
+}
+
+/// Creates a minimal mkdocs site with the README as its index page, leaving existing files alone
+pub(crate) fn scaffold(
+    component: &Component,
+    readme: Option<String>,
+    paths: &[String],
+) -> Result<Vec<Change>> {
+    let name = &component.metadata.name;
+    let description = &component.metadata.description;
+
+    let mkdocs = serde_yaml::to_string(&MkDocs {
+        site_name: name,
+        site_description: description,
+        nav: vec![BTreeMap::from([("Home", "index.md")])],
+        plugins: vec!["techdocs-core"],
+    })?;
+
+    let index = readme.unwrap_or_else(|| format!("# {name}\n\n{description}\n"));
+
+    Ok([("mkdocs.yml", mkdocs), ("docs/index.md", index)]
+        .into_iter()
+        .filter(|(path, _)| !paths.iter().any(|existing| existing == path))
+        .map(|(path, content)| Change::CreateFile {
+            path: path.to_owned(),
+            content,
+        })
+        .collect())
+}
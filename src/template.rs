@@ -0,0 +1,103 @@
+use crate::backstage::Component;
+use octocrab::models::Repository;
+use regex::{Captures, Regex};
+use std::collections::BTreeMap;
+
+/// Variables available to a template, by name
+pub(crate) type Vars = BTreeMap<&'static str, String>;
+
+/// The names of the variables from [`entity_vars`]
+pub(crate) const ENTITY_VARS: &[&str] = &[
+    "name",
+    "kind",
+    "description",
+    "owner",
+    "lifecycle",
+    "namespace",
+    "system",
+    "full_name",
+    "default_branch",
+];
+
+/// Replaces every `{name}` in the template with its variable, or returns None if the template
+/// uses a variable that isn't set. Optional variables, written `{name?}`, are left empty instead.
+pub(crate) fn render(template: &str, vars: &Vars) -> Option<String> {
+    render_known(template, vars, |_| true)
+}
+
+/// Renders the template like [`render`], leaving `{name}`s that aren't known variables as they
+/// are, eg `${HOME}` in a shell snippet
+pub(crate) fn render_known(
+    template: &str,
+    vars: &Vars,
+    is_known: impl Fn(&str) -> bool,
+) -> Option<String> {
+    let re = Regex::new(r"\{(\w+)(\?)?\}").unwrap();
+    let mut missing = false;
+
+    let rendered = re.replace_all(template, |captures: &Captures| {
+        if !is_known(&captures[1]) {
+            return captures[0].to_owned();
+        }
+
+        match vars.get(&captures[1]).filter(|value| !value.is_empty()) {
+            Some(value) => value.to_owned(),
+            None if captures.get(2).is_some() => String::new(),
+            None => {
+                missing = true;
+                String::new()
+            }
+        }
+    });
+
+    (!missing).then(|| rendered.into_owned())
+}
+
+/// The variables describing a catalog entity and its repo
+pub(crate) fn entity_vars(component: &Component, repo: &Repository) -> Vars {
+    Vars::from([
+        ("name", component.metadata.name.to_owned()),
+        ("kind", component.kind.to_owned()),
+        ("description", component.metadata.description.to_owned()),
+        ("owner", component.spec.owner.to_owned()),
+        ("lifecycle", component.spec.lifecycle.to_owned()),
+        (
+            "namespace",
+            component.metadata.namespace.clone().unwrap_or_else(|| "default".to_owned()),
+        ),
+        ("system", component.spec.system.clone().unwrap_or_default()),
+        ("full_name", repo.full_name.clone().unwrap_or_default()),
+        ("default_branch", repo.default_branch.clone().unwrap_or_default()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Vars {
+        Vars::from([("name", "api".to_owned()), ("system", String::new())])
+    }
+
+    #[test]
+    fn renders_variables() {
+        assert_eq!(render("{name}-{name}", &vars()).as_deref(), Some("api-api"));
+        assert_eq!(render("{name} {system?}", &vars()).as_deref(), Some("api "));
+    }
+
+    #[test]
+    fn fails_on_missing_variables() {
+        assert_eq!(render("{system}", &vars()), None);
+        assert_eq!(render("{unknown}", &vars()), None);
+    }
+
+    #[test]
+    fn leaves_unknown_variables_alone() {
+        let is_known = |name: &str| ENTITY_VARS.contains(&name);
+        assert_eq!(
+            render_known("echo ${HOME} {name}", &vars(), is_known).as_deref(),
+            Some("echo ${HOME} api")
+        );
+        assert_eq!(render_known("{system}", &vars(), is_known), None);
+    }
+}